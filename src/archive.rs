@@ -0,0 +1,120 @@
+use std::fs::File;
+use std::io::{BufReader,Read};
+use std::path::Path;
+use anyhow::Result;
+use flate2::read::GzDecoder;
+use tz::UtcDateTime;
+
+use crate::{
+    fsmap::*,
+    import::{Builder,Kind}
+};
+
+/// Virtual devices holding the members of archives are numbered from
+/// here on
+pub const ARCHIVE_DEV_BASE : u64 = 1 << 63;
+
+/// Archive formats whose members can be listed
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip
+}
+
+impl ArchiveFormat {
+    /// Guesses the format from the name of the file
+    pub fn of_name(name:&str)->Option<Self> {
+	let name = name.to_lowercase();
+	if name.ends_with(".tar") {
+	    Some(Self::Tar)
+	} else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+	    Some(Self::TarGz)
+	} else if name.ends_with(".zip") {
+	    Some(Self::Zip)
+	} else {
+	    None
+	}
+    }
+}
+
+fn list_tar<R:Read>(input:R,builder:&mut Builder)->Result<()> {
+    let mut archive = tar::Archive::new(input);
+    for entry in archive.entries()? {
+	let entry = entry?;
+	let header = entry.header();
+	let path = entry.path()?.into_owned();
+	let kind =
+	    match header.entry_type() {
+		tar::EntryType::Directory => Kind::Dir,
+		tar::EntryType::Symlink => {
+		    let target = entry.link_name()?.map(|p| p.into_owned()).unwrap_or_default();
+		    Kind::Symlink(target.into_os_string())
+		},
+		t => {
+		    let ino = builder.inode(entry.size(),header.mtime()? as i64);
+		    if t.is_file() || t.is_hard_link() || t.is_contiguous() {
+			Kind::File(ino)
+		    } else {
+			Kind::Other(ino)
+		    }
+		}
+	    };
+	builder.insert(&path,kind)?;
+    }
+    Ok(())
+}
+
+fn list_zip(fd:File,builder:&mut Builder)->Result<()> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(fd))?;
+    for i in 0..archive.len() {
+	let member = archive.by_index_raw(i)?;
+	let path = Path::new(member.name()).to_path_buf();
+	let kind =
+	    if member.is_dir() {
+		Kind::Dir
+	    } else {
+		let dt = member.last_modified();
+		let time = UtcDateTime::new(dt.year() as i32,dt.month(),dt.day(),
+					    dt.hour(),dt.minute(),dt.second(),0)
+		    .map(|t| t.unix_time())
+		    .unwrap_or(0);
+		Kind::File(builder.inode(member.size(),time))
+	    };
+	builder.insert(&path,kind)?;
+    }
+    Ok(())
+}
+
+/// Lists the members of the archive at path as a directory of device
+/// dev, along with their inodes
+pub fn list_members(path:&Path,format:ArchiveFormat,dev:u64)->Result<(Directory,Device)> {
+    let fd = File::open(path)?;
+    let mut builder = Builder::new(dev);
+    match format {
+	ArchiveFormat::Tar => list_tar(BufReader::new(fd),&mut builder)?,
+	ArchiveFormat::TarGz => list_tar(GzDecoder::new(BufReader::new(fd)),&mut builder)?,
+	ArchiveFormat::Zip => list_zip(fd,&mut builder)?
+    }
+    Ok(builder.into_parts())
+}
+
+#[test]
+fn test_list_tar() {
+    assert_eq!(ArchiveFormat::of_name("Backup.TGZ"),Some(ArchiveFormat::TarGz));
+    assert_eq!(ArchiveFormat::of_name("tar"),None);
+    let mut tar = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(3);
+    header.set_mtime(1_552_000_000);
+    header.set_entry_type(tar::EntryType::Regular);
+    tar.append_data(&mut header,"etc/passwd",&b"abc"[..]).unwrap();
+    let data = tar.into_inner().unwrap();
+    let mut builder = Builder::new(ARCHIVE_DEV_BASE);
+    list_tar(&data[..],&mut builder).unwrap();
+    let (dir,device) = builder.into_parts();
+    let Some((_,Entry::Dir(etc))) = dir.entries.first() else { panic!() };
+    let &Entry::File(ino) = &etc.entries[0].1 else { panic!() };
+    let fi = device.get_inode(ino).unwrap();
+    assert_eq!((fi.size,fi.unix_time()),(3,1_552_000_000 / 60 * 60));
+}
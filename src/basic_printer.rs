@@ -1,4 +1,5 @@
 use std::ffi::OsString;
+use std::io::Write;
 use anyhow::{bail,Result};
 use tz::{DateTime,TimeZoneRef};
 use log::warn;
@@ -11,7 +12,7 @@ use crate::{
     watcher::{Action,Watcher}
 };
 
-pub struct BasicPrinter<'a> {
+pub struct BasicPrinter<'a,W> {
     tz:TimeZoneRef<'a>,
     indent:usize,
     indent_mode:IndentMode,
@@ -19,10 +20,11 @@ pub struct BasicPrinter<'a> {
     ifs_shown:Option<usize>,
     dir:PathBuf,
     last_dir:PathBuf,
+    out:W
 }
 
-impl<'a> BasicPrinter<'a> {
-    pub fn new()->Self {
+impl<'a,W:Write> BasicPrinter<'a,W> {
+    pub fn new(out:W)->Self {
 	Self {
 	    tz:TimeZoneRef::utc(),
 	    indent:0,
@@ -31,6 +33,7 @@ impl<'a> BasicPrinter<'a> {
 	    ifs_shown:None,
 	    dir:PathBuf::new(),
 	    last_dir:PathBuf::new(),
+	    out
 	}
     }
 
@@ -40,8 +43,10 @@ impl<'a> BasicPrinter<'a> {
 
     fn show_dir(&mut self,fse:&FileSystemEntry)->Result<()> {
 	if self.ifs != self.ifs_shown {
-	    println!("DRV {:?}",
-		     fse.origin);
+	    match &fse.label {
+		Some(label) => writeln!(self.out,"DRV {} {:?}",label,fse.origin)?,
+		None => writeln!(self.out,"DRV {:?}",fse.origin)?
+	    }
 	    self.ifs_shown = self.ifs;
 	}
 	let c1 : Vec<Component> = self.last_dir.components().collect();
@@ -55,10 +60,10 @@ impl<'a> BasicPrinter<'a> {
 		i < m1 &&
 		c1[i] == c2[i];
 	    if !match_so_far {
-		print!("{:21} ","   ");
-		self.put_indent(i);
+		write!(self.out,"{:21} ","   ")?;
+		self.indent_mode.put_indent(&mut self.out,i)?;
 		if let Component::Normal(u) = c2[i] {
-		    println!("{}/",u.to_string_lossy());
+		    writeln!(self.out,"{}/",u.to_string_lossy())?;
 		}
 	    }
 	}
@@ -68,12 +73,13 @@ impl<'a> BasicPrinter<'a> {
 	Ok(())
     }
 
-    fn put_indent(&self,indent:usize) {
-	self.indent_mode.put_indent(indent);
+    fn put_indent(&mut self,indent:usize)->Result<()> {
+	self.indent_mode.put_indent(&mut self.out,indent)?;
+	Ok(())
     }
 }
 
-impl<'a> Watcher for BasicPrinter<'a> {
+impl<'a,W:Write> Watcher for BasicPrinter<'a,W> {
     fn interrupted(&mut self)->Result<()> {
 	bail!("Interrupted")
     }
@@ -117,41 +123,43 @@ impl<'a> Watcher for BasicPrinter<'a> {
 	self.show_dir(fse)?;
 	match entry {
 	    &Entry::Dir(_) => {
-		print!("{:21} ","DIR");
-		self.put_indent(self.indent);
-		println!("{}",data.name);
+		write!(self.out,"{:21} ","DIR")?;
+		self.put_indent(self.indent)?;
+		writeln!(self.out,"{}",data.name)?;
 	    },
-	    &Entry::File(ino) => {
+	    &Entry::File(ino) | &Entry::Archive(ino,_) => {
+		let suffix = if let Entry::Archive(..) = entry { "//" } else { "" };
 		if let Some(fi) = device.get_inode(ino) {
 		    let dt = DateTime::from_timespec(
 			fi.unix_time(),
 			0,
 			self.tz)?;
-		    print!("{:10} {:04}-{:02}-{:02} ",
+		    write!(self.out,
+			   "{:10} {:04}-{:02}-{:02} ",
 			   fi.size,
 			   dt.year(),
 			   dt.month(),
-			   dt.month_day());
-		    self.put_indent(self.indent);
-		    println!("{}",data.name);
+			   dt.month_day())?;
+		    self.put_indent(self.indent)?;
+		    writeln!(self.out,"{}{}",data.name,suffix)?;
 		} else {
-		    println!("{:10} {:10} {}","NO-INODE",ino,data.name);
+		    writeln!(self.out,"{:10} {:10} {}{}","NO-INODE",ino,data.name,suffix)?;
 		}
 	    },
 	    Entry::Symlink(sl) => {
-		print!("{:21} ","SYML");
-		self.put_indent(self.indent);
-		println!("{} -> {:?}",data.name,sl);
+		write!(self.out,"{:21} ","SYML")?;
+		self.put_indent(self.indent)?;
+		writeln!(self.out,"{} -> {:?}",data.name,sl)?;
 	    },
 	    Entry::Other(ino) => {
-		print!("{:21} ","OTHER");
-		self.put_indent(self.indent);
-		println!("{} ino {}",data.name,ino);
+		write!(self.out,"{:21} ","OTHER")?;
+		self.put_indent(self.indent)?;
+		writeln!(self.out,"{} ino {}",data.name,ino)?;
 	    },
 	    Entry::Error(err) => {
-		print!("{:21} ","ERROR");
-		self.put_indent(self.indent);
-		println!("{} : {}",data.name,err);
+		write!(self.out,"{:21} ","ERROR")?;
+		self.put_indent(self.indent)?;
+		writeln!(self.out,"{} : {}",data.name,err)?;
 	    },
 	}
 	Ok(Action::Enter)
@@ -19,4 +19,23 @@ impl<T> Expr<T> {
 	    Self::Diff(x,y) => x.eval(f) && !y.eval(f)
 	}
     }
+
+    /// Rebuilds the expression, replacing each atom by the expression
+    /// returned by f
+    pub fn try_map<U,E,F:FnMut(&T)->Result<Expr<U>,E>>(&self,f:&mut F)
+						      ->Result<Expr<U>,E> {
+	let e =
+	    match self {
+		Self::True => Expr::True,
+		Self::False => Expr::False,
+		Self::Atom(a) => f(a)?,
+		Self::And(x,y) =>
+		    Expr::And(Box::new(x.try_map(f)?),Box::new(y.try_map(f)?)),
+		Self::Or(x,y) =>
+		    Expr::Or(Box::new(x.try_map(f)?),Box::new(y.try_map(f)?)),
+		Self::Diff(x,y) =>
+		    Expr::Diff(Box::new(x.try_map(f)?),Box::new(y.try_map(f)?))
+	    };
+	Ok(e)
+    }
 }
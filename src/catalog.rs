@@ -0,0 +1,185 @@
+use anyhow::{anyhow,bail,Result};
+use std::fs::{self,File};
+use std::io::{BufWriter,Write};
+use std::path::{Path,PathBuf};
+use log::warn;
+
+use crate::fsmap::FileSystemEntry;
+
+/// A map registered in the catalog under a stable name
+#[derive(Debug,Default,Clone,PartialEq,Eq)]
+pub struct CatalogEntry {
+    pub name:String,
+    pub map:PathBuf,
+    // Label written on the physical disk
+    pub label:Option<String>,
+    pub serial:Option<String>,
+    pub description:Option<String>
+}
+
+/// List of known maps, kept in a file of the form
+///
+///   [photos-2019]
+///   map = /srv/maps/photos-2019.mpk
+///   label = Blue WD 2TB
+///   serial = WX31A1234567
+///   description = Photos 2012-2019
+///
+/// Relative map paths are taken from the directory of the catalog.
+#[derive(Debug,Default)]
+pub struct Catalog {
+    path:PathBuf,
+    pub entries:Vec<CatalogEntry>
+}
+
+impl CatalogEntry {
+    fn set(&mut self,key:&str,value:&str)->Result<()> {
+	let value = value.to_string();
+	match key {
+	    "map" => self.map = value.into(),
+	    "label" => self.label = Some(value),
+	    "serial" => self.serial = Some(value),
+	    "description" => self.description = Some(value),
+	    _ => bail!("Unknown key {:?}",key)
+	}
+	Ok(())
+    }
+}
+
+impl Catalog {
+    /// Location of the catalog, $FSMAP_CATALOG or ~/.fsmap/catalog
+    pub fn default_path()->PathBuf {
+	if let Some(path) = std::env::var_os("FSMAP_CATALOG") {
+	    return path.into();
+	}
+	let mut path = PathBuf::new();
+	if let Some(dir) = std::env::var_os("HOME") {
+	    path.push(dir);
+	}
+	path.join(".fsmap").join("catalog")
+    }
+
+    /// Loads the catalog at path; a missing file is an empty catalog
+    pub fn load<P:AsRef<Path>>(path:P)->Result<Self> {
+	let path = path.as_ref();
+	let mut catalog = Self { path:path.to_path_buf(),entries:Vec::new() };
+	if !path.exists() {
+	    return Ok(catalog);
+	}
+	let text = fs::read_to_string(path)?;
+	catalog.parse(&text)
+	    .map_err(|e| anyhow!("{}: {}",path.display(),e))?;
+	Ok(catalog)
+    }
+
+    fn parse(&mut self,text:&str)->Result<()> {
+	for (iline,line) in text.lines().enumerate() {
+	    let line = line.trim();
+	    if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+		continue;
+	    }
+	    let res =
+		if let Some(name) = line.strip_prefix('[').and_then(|u| u.strip_suffix(']')) {
+		    self.add(CatalogEntry { name:name.trim().to_string(),..Default::default() })
+		} else if let Some((key,value)) = line.split_once('=') {
+		    match self.entries.last_mut() {
+			Some(entry) => entry.set(key.trim(),value.trim()),
+			None => Err(anyhow!("Definition outside of a section"))
+		    }
+		} else {
+		    Err(anyhow!("Invalid line"))
+		};
+	    res.map_err(|e| anyhow!("line {}: {}",iline + 1,e))?;
+	}
+	for entry in self.entries.iter() {
+	    if entry.map.as_os_str().is_empty() {
+		warn!("No map given for {:?} in the catalog",entry.name);
+	    }
+	}
+	Ok(())
+    }
+
+    pub fn save(&self)->Result<()> {
+	if let Some(dir) = self.path.parent() {
+	    fs::create_dir_all(dir)?;
+	}
+	let fd = File::create(&self.path)?;
+	let mut buf = BufWriter::new(fd);
+	writeln!(buf,"# Maps known to fsmap")?;
+	for entry in self.entries.iter() {
+	    writeln!(buf)?;
+	    writeln!(buf,"[{}]",entry.name)?;
+	    writeln!(buf,"map = {}",entry.map.display())?;
+	    for (key,value) in [("label",&entry.label),
+				("serial",&entry.serial),
+				("description",&entry.description)] {
+		if let Some(value) = value {
+		    writeln!(buf,"{} = {}",key,value)?;
+		}
+	    }
+	}
+	buf.flush()?;
+	Ok(())
+    }
+
+    pub fn add(&mut self,entry:CatalogEntry)->Result<()> {
+	if !FileSystemEntry::is_valid_label(&entry.name) {
+	    bail!("Invalid name {:?}",entry.name);
+	}
+	if self.get(&entry.name).is_some() {
+	    bail!("{:?} is already in the catalog",entry.name);
+	}
+	self.entries.push(entry);
+	Ok(())
+    }
+
+    pub fn remove(&mut self,name:&str)->Result<CatalogEntry> {
+	match self.entries.iter().position(|e| e.name == name) {
+	    Some(i) => Ok(self.entries.remove(i)),
+	    None => bail!("{:?} is not in the catalog",name)
+	}
+    }
+
+    pub fn get(&self,name:&str)->Option<&CatalogEntry> {
+	self.entries.iter().find(|e| e.name == name)
+    }
+
+    /// Path of the map of entry, relative paths being taken from the
+    /// directory of the catalog
+    pub fn map_path(&self,entry:&CatalogEntry)->PathBuf {
+	match self.path.parent() {
+	    Some(dir) => dir.join(&entry.map),
+	    None => entry.map.clone()
+	}
+    }
+
+    /// Finds the entry whose map is the file at path
+    pub fn find_map<P:AsRef<Path>>(&self,path:P)->Option<&CatalogEntry> {
+	let path = path.as_ref().canonicalize().ok()?;
+	self.entries.iter().find(|e| {
+	    self.map_path(e).canonicalize().is_ok_and(|p| p == path)
+	})
+    }
+}
+
+#[test]
+fn test_catalog() {
+    let mut catalog = Catalog::default();
+    catalog.parse("# Disks
+[photos-2019]
+map = photos.mpk
+label = Blue WD 2TB
+; Bought in 2019
+serial = WX31A = 1234
+[backup]
+map=/srv/backup.mpk
+").unwrap();
+    assert_eq!(catalog.entries.len(),2);
+    let e = catalog.get("photos-2019").unwrap();
+    assert_eq!(e.label.as_deref(),Some("Blue WD 2TB"));
+    assert_eq!(e.serial.as_deref(),Some("WX31A = 1234"));
+    assert_eq!(catalog.get("backup").unwrap().map,Path::new("/srv/backup.mpk"));
+    for bad in &["map = a.mpk","[a]\ncolor = red","[a]\n[a]","[2019]","[a]\nmap"] {
+	assert!(Catalog::default().parse(bad).is_err());
+    }
+}
@@ -0,0 +1,40 @@
+use anyhow::Result;
+use sha2::{Digest as _,Sha256};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// SHA-256 of the contents of a file
+pub type Digest = [u8;32];
+
+pub fn file_digest<P:AsRef<Path>>(path:P)->Result<Digest> {
+    let mut fd = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut fd,&mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+pub fn to_hex(digest:&Digest)->String {
+    digest.iter().map(|b| format!("{:02x}",b)).collect()
+}
+
+pub fn parse_hex(u:&str)->Option<Digest> {
+    let mut digest = [0;32];
+    if u.len() != 64 || !u.is_ascii() {
+	return None;
+    }
+    for (i,d) in digest.iter_mut().enumerate() {
+	*d = u8::from_str_radix(&u[2*i..2*i + 2],16).ok()?;
+    }
+    Some(digest)
+}
+
+#[test]
+fn test_digest() {
+    let path = std::env::temp_dir().join(format!("fsmap-digest-{}",std::process::id()));
+    std::fs::write(&path,b"abc").unwrap();
+    let digest = file_digest(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(&digest[..8],&[0xba,0x78,0x16,0xbf,0x8f,0x01,0xcf,0xea]);
+    assert_eq!(parse_hex(&to_hex(&digest)),Some(digest));
+}
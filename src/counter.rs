@@ -1,4 +1,4 @@
-use std::io::{Write};
+use std::io::{self,Write};
 use std::path::{Path};
 
 use crate::{
@@ -10,12 +10,18 @@ pub struct Counter {
     total:u64,
     errors:u64,
     count:u64,
-    valve:Valve
+    valve:Valve,
+    out:Box<dyn Write>
 }
 
 impl Counter {
     pub fn new()->Self {
-	Self{ total:0,errors:0,count:0,valve:Valve::new(0.1) }
+	Self::with_output(Box::new(io::stdout()))
+    }
+
+    /// Shows the progress on out instead of standard output
+    pub fn with_output(out:Box<dyn Write>)->Self {
+	Self{ total:0,errors:0,count:0,valve:Valve::new(0.1),out }
     }
 
     fn tick(&mut self,path:&Path) {
@@ -23,8 +29,8 @@ impl Counter {
 	if self.count & self.valve.mask == 0 {
 	    self.valve.tick();
 	    let u = path.to_string_lossy();
-	    print!("\r{:8} {:8} {}\x1b[K",self.total,self.errors,u);
-	    std::io::stdout().flush().unwrap();
+	    let _ = write!(self.out,"\r{:8} {:8} {}\x1b[K",self.total,self.errors,u);
+	    let _ = self.out.flush();
 	}
     }
 }
@@ -43,6 +49,6 @@ impl Watcher for Counter {
 
 impl Drop for Counter {
     fn drop(&mut self) {
-	println!("\nTotal: {}, errors: {}\x1b[K",self.total,self.errors);
+	let _ = writeln!(self.out,"\nTotal: {}, errors: {}\x1b[K",self.total,self.errors);
     }
 }
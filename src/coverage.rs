@@ -0,0 +1,284 @@
+use std::collections::{BTreeMap,HashMap};
+use std::ffi::OsString;
+use std::io::Write;
+use anyhow::{bail,Result};
+
+use crate::{
+    checksum::Digest,
+    fsexpr::FsData,
+    fsmap::*,
+    stats::{human_bytes,Tally},
+    watcher::{Action,Watcher}
+};
+
+/// Rewrites paths starting with from so that they start with to
+#[derive(Debug,PartialEq,Eq)]
+pub struct Remap {
+    from:String,
+    to:String
+}
+
+struct FileCopy {
+    idrive:usize,
+    path:String,
+    size:u64,
+    group:usize
+}
+
+#[derive(Default)]
+struct DriveTotals {
+    name:String,
+    all:Tally,
+    at_risk:Tally
+}
+
+/// Watcher counting on how many drives each file has a copy.  Files
+/// are the same if they have the same path and size, or the same
+/// digest.
+pub struct Coverage {
+    min_copies:usize,
+    remaps:Vec<Remap>,
+    drives:BTreeMap<usize,DriveTotals>,
+    current:usize,
+    // Path of the directory the current map was collected from,
+    // without the leading slash, if the map records it
+    base:Option<String>,
+    files:Vec<FileCopy>,
+    // Groups of identical files, merged when a digest shows that two
+    // groups hold the same contents
+    parents:Vec<usize>,
+    by_path:HashMap<(String,u64),usize>,
+    by_digest:HashMap<Digest,usize>
+}
+
+impl Remap {
+    /// Parses FROM=TO.  Paths are taken relative to the root of the
+    /// maps, a leading slash being optional.  FROM may also be the
+    /// absolute path of a directory in a map that records where it
+    /// was collected.
+    pub fn parse(u:&str)->Result<Self> {
+	let Some((from,to)) = u.split_once('=') else {
+	    bail!("Expected FROM=TO in remapping {:?}",u)
+	};
+	let clean = |u:&str| u.trim_matches('/').to_string();
+	Ok(Self { from:clean(from),to:clean(to) })
+    }
+
+    fn apply(&self,path:&str)->Option<String> {
+	let rest =
+	    if self.from.is_empty() {
+		path
+	    } else {
+		match path.strip_prefix(&self.from)? {
+		    "" => "",
+		    rest => rest.strip_prefix('/')?
+		}
+	    };
+	Some(
+	    if self.to.is_empty() || rest.is_empty() {
+		format!("{}{}",self.to,rest)
+	    } else {
+		format!("{}/{}",self.to,rest)
+	    })
+    }
+}
+
+impl Coverage {
+    pub fn new(min_copies:usize,remaps:Vec<Remap>)->Self {
+	Self {
+	    min_copies,
+	    remaps,
+	    drives:BTreeMap::new(),
+	    current:0,
+	    base:None,
+	    files:Vec::new(),
+	    parents:Vec::new(),
+	    by_path:HashMap::new(),
+	    by_digest:HashMap::new()
+	}
+    }
+
+    fn new_group(&mut self)->usize {
+	self.parents.push(self.parents.len());
+	self.parents.len() - 1
+    }
+
+    fn find(&mut self,mut g:usize)->usize {
+	while self.parents[g] != g {
+	    self.parents[g] = self.parents[self.parents[g]];
+	    g = self.parents[g];
+	}
+	g
+    }
+
+    // Path under which copies are compared.  Remappings are tried on
+    // the path relative to the root, then on the absolute path.
+    fn logical_path(&self,path:&str)->String {
+	let full = self.base.as_ref().map(|b| format!("{}/{}",b,path));
+	self.remaps.iter()
+	    .find_map(|r| r.apply(path).or_else(|| r.apply(full.as_deref()?)))
+	    .unwrap_or_else(|| path.to_string())
+    }
+
+    fn add(&mut self,idrive:usize,path:&str,size:u64,digest:Option<Digest>) {
+	let key = (self.logical_path(path),size);
+	let g1 = self.by_path.get(&key).copied();
+	let g2 = digest.and_then(|d| self.by_digest.get(&d).copied());
+	let group =
+	    match (g1,g2) {
+		(Some(g1),Some(g2)) => {
+		    let (g1,g2) = (self.find(g1),self.find(g2));
+		    self.parents[g2] = g1;
+		    g1
+		},
+		(Some(g),None) | (None,Some(g)) => g,
+		(None,None) => self.new_group()
+	    };
+	self.by_path.entry(key).or_insert(group);
+	if let Some(d) = digest {
+	    self.by_digest.entry(d).or_insert(group);
+	}
+	self.files.push(FileCopy { idrive,path:path.to_string(),size,group });
+    }
+
+    /// Number of distinct drives holding a copy, for each group
+    fn copies(&mut self)->Vec<usize> {
+	let mut drives : Vec<Vec<usize>> = vec![Vec::new();self.parents.len()];
+	for i in 0..self.files.len() {
+	    let g = self.find(self.files[i].group);
+	    self.files[i].group = g;
+	    let idrive = self.files[i].idrive;
+	    if !drives[g].contains(&idrive) {
+		drives[g].push(idrive);
+	    }
+	}
+	drives.iter().map(|d| d.len()).collect()
+    }
+
+    /// Writes the totals of each drive, and the files with fewer than
+    /// the minimum number of copies if list is set.  Returns the number
+    /// of such files.
+    pub fn write<W:Write>(&mut self,out:&mut W,list:bool)->Result<usize> {
+	let copies = self.copies();
+	let mut at_risk = Tally::default();
+	let mut all = Tally::default();
+	let mut seen = vec![false;copies.len()];
+	for file in self.files.iter() {
+	    let dt = self.drives.entry(file.idrive).or_insert_with(|| DriveTotals {
+		name:file.idrive.to_string(),
+		..Default::default()
+	    });
+	    dt.all.add(file.size);
+	    let risky = copies[file.group] < self.min_copies;
+	    if risky {
+		dt.at_risk.add(file.size);
+	    }
+	    if !seen[file.group] {
+		seen[file.group] = true;
+		all.add(file.size);
+		if risky {
+		    at_risk.add(file.size);
+		}
+	    }
+	}
+	writeln!(out,"{:16} {:>10} {:>12} {:>10} {:>12}",
+		 "Drive","Files","Bytes","At risk","Bytes")?;
+	for dt in self.drives.values() {
+	    writeln!(out,"{:16} {:10} {:>12} {:10} {:>12}",
+		     dt.name,
+		     dt.all.count,
+		     human_bytes(dt.all.bytes),
+		     dt.at_risk.count,
+		     human_bytes(dt.at_risk.bytes))?;
+	}
+	writeln!(out,"{:16} {:10} {:>12} {:10} {:>12}",
+		 "Distinct",
+		 all.count,
+		 human_bytes(all.bytes),
+		 at_risk.count,
+		 human_bytes(at_risk.bytes))?;
+	writeln!(out,"{} files ({}) have fewer than {} copies",
+		 at_risk.count,
+		 human_bytes(at_risk.bytes),
+		 self.min_copies)?;
+	if list {
+	    for file in self.files.iter() {
+		let n = copies[file.group];
+		if n < self.min_copies {
+		    writeln!(out,"{} {:>12} {}:{}",
+			     n,
+			     file.size,
+			     self.drives[&file.idrive].name,
+			     file.path)?;
+		}
+	    }
+	}
+	Ok(at_risk.count)
+    }
+}
+
+impl Watcher for Coverage {
+    fn enter_fs(&mut self,ifs:usize,fse:&FileSystemEntry)->Result<Action> {
+	self.current = ifs;
+	self.base = fse.fs.base.as_ref()
+	    .map(|b| b.to_string_lossy().trim_matches('/').to_string())
+	    .filter(|b| !b.is_empty());
+	self.drives.insert(ifs,DriveTotals {
+	    name:fse.label.clone().unwrap_or_else(|| ifs.to_string()),
+	    ..Default::default()
+	});
+	Ok(Action::Enter)
+    }
+
+    fn matching_entry(&mut self,
+		      _fse:&FileSystemEntry,
+		      _name:&OsString,
+		      device:&Device,
+		      entry:&Entry,
+		      data:&FsData)->Result<Action> {
+	if let &Entry::File(ino) | &Entry::Archive(ino,_) = entry {
+	    if let Some(fi) = device.get_inode(ino) {
+		self.add(self.current,data.path,fi.size,fi.digest);
+	    }
+	}
+	Ok(Action::Enter)
+    }
+}
+
+#[test]
+fn test_coverage() {
+    let remap = Remap::parse("/mnt/a/photos/=/photos").unwrap();
+    assert_eq!(remap.apply("mnt/a/photos/x.jpg").as_deref(),Some("photos/x.jpg"));
+    assert_eq!(remap.apply("mnt/a/photos").as_deref(),Some("photos"));
+    assert_eq!(remap.apply("mnt/a/photos2/x.jpg"),None);
+    assert_eq!(Remap::parse("/=old").unwrap().apply("x.jpg").as_deref(),Some("old/x.jpg"));
+    assert_eq!(Remap::parse("old=").unwrap().apply("old/x.jpg").as_deref(),Some("x.jpg"));
+    assert!(Remap::parse("photos").is_err());
+    let mut cov = Coverage::new(2,vec![Remap::parse("/backup/photos=/photos").unwrap()]);
+    cov.add(0,"photos/a.jpg",10,None);
+    cov.add(1,"backup/photos/a.jpg",10,None);
+    cov.add(0,"b.txt",5,Some([1;32]));
+    cov.add(1,"old/b.txt",5,Some([1;32]));
+    cov.add(1,"b.txt",5,None);
+    cov.add(0,"c.txt",5,None);
+    cov.add(0,"d.txt",5,Some([2;32]));
+    cov.add(0,"e.txt",5,Some([2;32]));
+    cov.add(0,"f.txt",7,None);
+    cov.add(1,"g.txt",7,Some([3;32]));
+    cov.add(1,"f.txt",7,Some([3;32]));
+    let mut out = Vec::new();
+    assert_eq!(cov.write(&mut out,true).unwrap(),2);
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.contains("1            5 0:c.txt\n"));
+    assert!(!text.contains("f.txt"));
+
+    // Maps of /mnt/a and of /, the second holding a backup
+    let mut cov = Coverage::new(2,vec![Remap::parse("/mnt/a/photos=/backup/photos").unwrap()]);
+    cov.base = Some("mnt/a".into());
+    assert_eq!(cov.logical_path("photos/x.jpg"),"backup/photos/x.jpg");
+    assert_eq!(cov.logical_path("docs/y.txt"),"docs/y.txt");
+    cov.add(0,"photos/x.jpg",10,None);
+    cov.base = None;
+    cov.add(1,"backup/photos/x.jpg",10,None);
+    assert_eq!(cov.copies(),[2]);
+}
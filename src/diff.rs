@@ -0,0 +1,189 @@
+use std::collections::{HashMap,HashSet};
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path,PathBuf};
+use anyhow::Result;
+use tz::UtcDateTime;
+
+use crate::fsmap::*;
+
+/// A difference between the expected tree and the actual one
+#[derive(Debug,PartialEq,Eq)]
+pub enum Change {
+    Missing,
+    Extra,
+    // Expected and actual kinds of entry
+    Kind(&'static str,&'static str),
+    Size(u64,u64),
+    Time(i64,i64),
+    // Same size, different contents, not reported along with a
+    // change of size
+    Digest,
+    Target(OsString,OsString),
+    // The actual entry could not be read
+    Unreadable(String)
+}
+
+/// What to leave out of the comparison
+#[derive(Clone,Debug,Default)]
+pub struct Options {
+    pub ignore_time:bool,
+    // Inodes of the expected map, by device and number, whose size or
+    // time is not known
+    pub unknown_size:HashSet<(u64,u64)>,
+    pub unknown_time:HashSet<(u64,u64)>
+}
+
+struct Differ<'a> {
+    expected:&'a Mounts,
+    actual:&'a Mounts,
+    options:&'a Options,
+    changes:Vec<(PathBuf,Change)>
+}
+
+fn inode(entry:&Entry)->Option<u64> {
+    match entry {
+	&Entry::File(ino) | &Entry::Other(ino) | &Entry::Archive(ino,_) => Some(ino),
+	_ => None
+    }
+}
+
+fn info<'a>(mounts:&'a Mounts,dev:u64,entry:&Entry)->Option<&'a FileInfo> {
+    mounts.get_device(dev)?.get_inode(inode(entry)?)
+}
+
+impl<'a> Differ<'a> {
+    fn dir(&mut self,path:&mut PathBuf,exp:&Directory,act:&Directory) {
+	let actual : HashMap<&OsString,&Entry> = act.entries.iter().map(|(n,e)| (n,e)).collect();
+	for (name,e) in exp.entries.iter() {
+	    path.push(name);
+	    match actual.get(name) {
+		None => self.changes.push((path.clone(),Change::Missing)),
+		Some(a) => self.entry(path,exp.dev,e,act.dev,a)
+	    }
+	    path.pop();
+	}
+	let expected : HashSet<&OsString> = exp.entries.iter().map(|(n,_)| n).collect();
+	for (name,_) in act.entries.iter() {
+	    if !expected.contains(name) {
+		self.changes.push((path.join(name),Change::Extra));
+	    }
+	}
+    }
+
+    fn entry(&mut self,path:&mut PathBuf,exp_dev:u64,e:&Entry,act_dev:u64,a:&Entry) {
+	let change =
+	    match (e,a) {
+		// Nothing is known of entries that were not scanned
+		(Entry::Error(_),_) => None,
+		(_,Entry::Error(msg)) => Some(Change::Unreadable(msg.clone())),
+		(Entry::Dir(x),Entry::Dir(y)) => {
+		    self.dir(path,x,y);
+		    None
+		},
+		(Entry::Symlink(x),Entry::Symlink(y)) if x != y =>
+		    Some(Change::Target(x.clone(),y.clone())),
+		_ if e.kind_name() != a.kind_name() => Some(Change::Kind(e.kind_name(),a.kind_name())),
+		_ => {
+		    if let (Some(x),Some(y)) = (info(self.expected,exp_dev,e),info(self.actual,act_dev,a)) {
+			self.info(path,(exp_dev,inode(e).unwrap_or(0)),x,y);
+		    }
+		    None
+		}
+	    };
+	if let Some(change) = change {
+	    self.changes.push((path.clone(),change));
+	}
+    }
+
+    // Compares the inodes of a file, reporting each difference
+    fn info(&mut self,path:&Path,key:(u64,u64),x:&FileInfo,y:&FileInfo) {
+	let size = x.size != y.size && !self.options.unknown_size.contains(&key);
+	if size {
+	    self.changes.push((path.to_path_buf(),Change::Size(x.size,y.size)));
+	}
+	// Modification times, as time also changes when files are only
+	// read
+	if let (Some(t),Some(u)) = (x.mtime,y.mtime) {
+	    if t != u && !self.options.ignore_time && !self.options.unknown_time.contains(&key) {
+		self.changes.push((path.to_path_buf(),Change::Time(t,u)));
+	    }
+	}
+	if let (Some(d),Some(e)) = (&x.digest,&y.digest) {
+	    if d != e && !size {
+		self.changes.push((path.to_path_buf(),Change::Digest));
+	    }
+	}
+    }
+}
+
+/// Compares the tree of actual to that of expected, returning the
+/// changes in the order of expected
+pub fn diff(expected:&FileSystem,actual:&FileSystem,options:&Options)->Vec<(PathBuf,Change)> {
+    let mut differ = Differ {
+	expected:&expected.mounts,
+	actual:&actual.mounts,
+	options,
+	changes:Vec::new()
+    };
+    differ.dir(&mut PathBuf::new(),&expected.root,&actual.root);
+    differ.changes
+}
+
+fn time(t:i64)->String {
+    match UtcDateTime::from_timespec(t,0) {
+	Ok(dt) => format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+			  dt.year(),dt.month(),dt.month_day(),dt.hour(),dt.minute(),dt.second()),
+	Err(_) => t.to_string()
+    }
+}
+
+/// Writes one line for each change
+pub fn write_changes<W:Write>(out:&mut W,changes:&[(PathBuf,Change)])->Result<()> {
+    for (path,change) in changes {
+	let path = Path::new("/").join(path);
+	match change {
+	    Change::Missing => writeln!(out,"missing {:?}",path)?,
+	    Change::Extra => writeln!(out,"extra   {:?}",path)?,
+	    Change::Kind(x,y) => writeln!(out,"type    {:?} {} -> {}",path,x,y)?,
+	    Change::Size(x,y) => writeln!(out,"size    {:?} {} -> {}",path,x,y)?,
+	    Change::Time(x,y) => writeln!(out,"time    {:?} {} -> {}",path,time(*x),time(*y))?,
+	    Change::Digest => writeln!(out,"digest  {:?}",path)?,
+	    Change::Target(x,y) => writeln!(out,"target  {:?} {:?} -> {:?}",path,x,y)?,
+	    Change::Unreadable(e) => writeln!(out,"error   {:?} {}",path,e)?
+	}
+    }
+    Ok(())
+}
+
+#[test]
+fn test_diff() {
+    let map = |size:u64,time:i32,digest:u8,last:Entry| {
+	let mut mounts = Mounts::new();
+	mounts.ensure_device(1);
+	mounts.get_device_mut(1).unwrap().insert_inode(2,FileInfo { size,time,digest:Some([digest;32]),mtime:Some(time as i64 * 60) });
+	let mut root = Directory::new(1);
+	root.insert("f".into(),Entry::File(2));
+	root.insert("x".into(),last);
+	FileSystem { mounts,root,base:None }
+    };
+    let expected = map(1,10,0,Entry::Dir(Directory::new(1)));
+    let actual = map(1,11,1,Entry::Symlink("f".into()));
+    let changes = diff(&expected,&actual,&Options::default());
+    assert_eq!(changes,[(PathBuf::from("f"),Change::Time(600,660)),
+			(PathBuf::from("f"),Change::Digest),
+			(PathBuf::from("x"),Change::Kind("dir","link"))]);
+    // A change of size implies one of contents
+    let actual = map(2,11,1,Entry::Dir(Directory::new(1)));
+    let changes = diff(&expected,&actual,&Options::default());
+    assert_eq!(changes,[(PathBuf::from("f"),Change::Size(1,2)),
+			(PathBuf::from("f"),Change::Time(600,660))]);
+    let options = Options { unknown_size:HashSet::from([(1,2)]),..Default::default() };
+    let changes = diff(&expected,&actual,&options);
+    assert_eq!(changes,[(PathBuf::from("f"),Change::Time(600,660)),
+			(PathBuf::from("f"),Change::Digest)]);
+    let actual = map(1,11,1,Entry::Error("Permission denied".into()));
+    let changes = diff(&expected,&actual,&Options { ignore_time:true,..Default::default() });
+    assert_eq!(changes,[(PathBuf::from("f"),Change::Digest),
+			(PathBuf::from("x"),Change::Unreadable("Permission denied".into()))]);
+}
@@ -1,11 +1,11 @@
-use std::ffi::OsString;
-use std::path::{PathBuf};
-use anyhow::{Result};
-use log::warn;
+use std::ffi::{OsStr,OsString};
+use std::path::{Component,PathBuf};
+use anyhow::{bail,Result};
 
 use crate::{
     fsexpr::{FsData,Predicate},
     fsmap::*,
+    resolver::Resolver,
     sigint_detector::SigintDetector,
     watcher::{Action,Watcher}
 };
@@ -15,8 +15,11 @@ pub struct Dumper<'a,'b,'c,P,W> {
     fss:&'b FileSystems,
     pred:&'c P,
     idrive:usize,
-    current:PathBuf,
+    scope:Option<(usize,PathBuf)>,
     watcher:W,
+    // Path of the entry being visited, extended on entering a
+    // directory and cut back on leaving it
+    path:String,
     pub matching_bytes:u64,
     pub matching_entries:usize,
 }
@@ -31,10 +34,11 @@ impl<'a,'b,'c,P,W> Dumper<'a,'b,'c,P,W> where P:Predicate,W:Watcher {
 	    sd,
 	    pred,
 	    idrive:0,
-	    current:PathBuf::new(),
+	    scope:None,
 	    matching_bytes:0,
 	    matching_entries:0,
-	    watcher
+	    watcher,
+	    path:String::new()
 	}
     }
 
@@ -42,24 +46,104 @@ impl<'a,'b,'c,P,W> Dumper<'a,'b,'c,P,W> where P:Predicate,W:Watcher {
 	self.watcher
     }
 	
+    /// Restricts the dump to the directory at path on the given drive.
+    /// The path must not contain symbolic links.
+    pub fn set_scope(&mut self,idrive:usize,path:PathBuf) {
+	self.scope = Some((idrive,path));
+    }
+
     pub fn dump(&mut self)->Result<()> {
+	let scope = self.scope.take();
 	for (ifs,fse) in self.fss.systems.iter().enumerate() {
+	    let path =
+		match &scope {
+		    None => None,
+		    Some((idrive,path)) if *idrive == ifs => Some(path),
+		    Some(_) => continue
+		};
 	    if let Action::Enter = self.watcher.enter_fs(ifs,fse)? {
 		self.idrive = ifs;
-		self.dump_dir(fse,&fse.fs.root)?;
+		let resolver = Resolver::new(&fse.fs);
+		match path {
+		    None => self.dump_dir(fse,&resolver,&fse.fs.root,None)?,
+		    Some(path) => {
+			let names : Vec<&OsStr> = path.components()
+			    .filter_map(|c| match c {
+				Component::Normal(u) => Some(u),
+				_ => None
+			    })
+			    .collect();
+			self.dump_below(fse,&resolver,&fse.fs.root,&names,None)?
+		    }
+		}
 		self.watcher.leave_fs()?;
 	    }
 	}
+	self.scope = scope;
 	Ok(())
     }
 
-    fn dump_dir(&mut self,fse:&FileSystemEntry,dir:&Directory)->Result<()> {
+    // Walks down to the directory at the given path without reporting
+    // the directories along the way, then dumps it
+    fn dump_below(&mut self,
+		  fse:&FileSystemEntry,
+		  resolver:&Resolver,
+		  dir:&Directory,
+		  names:&[&OsStr],
+		  parent:Option<&FsData>)->Result<()> {
+	let Some((&first,rest)) = names.split_first() else {
+	    return self.dump_dir(fse,resolver,dir,parent);
+	};
+	let Some(device) = fse.fs.mounts.get_device(dir.dev) else {
+	    return self.watcher.device_not_found(dir.dev);
+	};
+	let Some((name,entry)) = dir.entries.iter().find(|(n,_)| n.as_os_str() == first) else {
+	    bail!("Cannot find {:?}",first);
+	};
+	let Entry::Dir(sub) = entry else {
+	    bail!("Not a directory: {:?}",first);
+	};
+	let nsl = name.to_string_lossy();
+	let len = self.push_name(parent,&nsl);
+	let data = FsData::new(
+	    self.idrive as u64,
+	    &nsl,
+	    name,
+	    "",
+	    parent.map(|p| p.depth + 1).unwrap_or(1),
+	    entry,
+	    device,
+	    fse,
+	    resolver,
+	    parent).into_parent(self.path.len());
+	self.dump_below(fse,resolver,sub,rest,Some(&data))?;
+	self.path.truncate(len);
+	Ok(())
+    }
+
+    // Appends the name of a child of parent to the path, returning
+    // the previous length of the path
+    fn push_name(&mut self,parent:Option<&FsData>,name:&str)->usize {
+	let len = self.path.len();
+	if let Some(p) = parent {
+	    self.path.push_str(p.separator());
+	}
+	self.path.push_str(name);
+	len
+    }
+
+    fn dump_dir(&mut self,
+		fse:&FileSystemEntry,
+		resolver:&Resolver,
+		dir:&Directory,
+		parent:Option<&FsData>)->Result<()> {
 	if let Some(device) = fse.fs.mounts.get_device(dir.dev) {
 	    for (name,entry) in dir.entries.iter() {
 		if self.sd.interrupted() {
 		    self.watcher.interrupted()?;
 		}
-		if let Action::Skip = self.dump_entry(fse,name,device,entry)? {
+		if let Action::Skip =
+		    self.dump_entry(fse,resolver,name,device,entry,parent)? {
 		    break;
 		}
 	    }
@@ -71,35 +155,27 @@ impl<'a,'b,'c,P,W> Dumper<'a,'b,'c,P,W> where P:Predicate,W:Watcher {
 
     fn dump_entry(&mut self,
 		  fse:&FileSystemEntry,
+		  resolver:&Resolver,
 		  name:&OsString,
 		  device:&Device,
-		  entry:&Entry)->Result<Action> {
-	self.current.push(name);
+		  entry:&Entry,
+		  parent:Option<&FsData>)->Result<Action> {
 	let nsl = name.to_string_lossy();
-	let path = self.current.as_os_str().to_string_lossy();
+	let len = self.push_name(parent,&nsl);
 
-	let mut data = FsData {
-	    drive:self.idrive as u64,
-	    name:&nsl,
-	    path:&path,
-	    timestamp:None,
-	    size:None
-	};
-
-	match entry {
-	    &Entry::File(ino) => {
-		if let Some(fi) = device.get_inode(ino) {
-		    data.size = Some(fi.size);
-		    data.timestamp = Some(fi.unix_time());
-		} else {
-		    warn!("Inode {} not found",ino);
-		}
-	    },
-	    Entry::Dir(_) |
-	    Entry::Symlink(_) |
-	    Entry::Other(_) |
-	    Entry::Error(_) => ()
-	}
+	// The data passed down to the children does not borrow the path
+	let node = FsData::new(
+	    self.idrive as u64,
+	    &nsl,
+	    name,
+	    "",
+	    parent.map(|p| p.depth + 1).unwrap_or(1),
+	    entry,
+	    device,
+	    fse,
+	    resolver,
+	    parent).into_parent(self.path.len());
+	let data = node.with_path(&self.path);
 
 	let mut action = Action::Enter;
 	let show = self.pred.test(&data);
@@ -114,14 +190,47 @@ impl<'a,'b,'c,P,W> Dumper<'a,'b,'c,P,W> where P:Predicate,W:Watcher {
 		&data)?;
 	}
 	if let Action::Enter = action {
-	    if let Entry::Dir(dir) = entry {
-		if let Action::Enter = self.watcher.enter_dir(name)? {
-		    self.dump_dir(fse,dir)?;
-		    self.watcher.leave_dir()?;
+	    if let Some(dir) = entry.as_dir() {
+		if self.pred.may_match_below(&data) {
+		    if let Action::Enter = self.watcher.enter_dir(name)? {
+			self.dump_dir(fse,resolver,dir,Some(&node))?;
+			self.watcher.leave_dir()?;
+		    }
 		}
 	    }
 	}
-	self.current.pop();
+	self.path.truncate(len);
 	Ok(action)
     }
 }
+
+/// Maps of a tree given by lines of the form "TYPE SIZE PATH", with
+/// the type letters and link syntax of find -printf, for tests
+#[cfg(test)]
+pub fn test_maps(listing:&str)->FileSystems {
+    let mut find = String::from("d 0 0 /t\n");
+    for line in listing.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+	let mut parts = line.splitn(3,' ');
+	let (kind,size,path) = (parts.next().unwrap(),parts.next().unwrap(),parts.next().unwrap());
+	find.push_str(&format!("{} {} 0 /t/{}\n",kind,size,path));
+    }
+    let fs = crate::import::import(crate::import::Format::FindPrintf,find.as_bytes()).unwrap();
+    FileSystems { systems:vec![FileSystemEntry { origin:"test.mpk".into(),fs,label:None }] }
+}
+
+/// Paths of the entries matching pred, in the order of the dump
+#[cfg(test)]
+pub fn matching_paths<P:Predicate>(fss:&FileSystems,pred:&P)->Vec<String> {
+    struct Paths(Vec<String>);
+    impl Watcher for Paths {
+	fn matching_entry(&mut self,_fse:&FileSystemEntry,_name:&OsString,_device:&Device,
+			  _entry:&Entry,data:&FsData)->Result<Action> {
+	    self.0.push(data.path.to_string());
+	    Ok(Action::Enter)
+	}
+    }
+    let sd = SigintDetector::new();
+    let mut dp = Dumper::new(&sd,fss,pred,Paths(Vec::new()));
+    dp.dump().unwrap();
+    dp.into_inner().0
+}
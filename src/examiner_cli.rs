@@ -1,22 +1,62 @@
 use anyhow::{Result,bail};
+use std::ffi::OsStr;
+use std::io::{self,Write};
+use std::path::{Path,PathBuf};
+use log::warn;
 
 use crate::{
     basic_printer::BasicPrinter,
+    catalog::{Catalog,CatalogEntry},
     dumper::Dumper,
-    fsexpr::FsExpr,
+    boolean::Expr,
+    fsexpr::{FsAtom,FsExpr},
     fsmap::*,
     help,
     indent::IndentMode,
     limiter::{Limiter,LimiterSettings},
     list_printer::ListPrinter,
+    output::{self,Output,Redirect},
+    query::Query,
+    resolver::{Node,Resolution,Resolver},
     sigint_detector::SigintDetector,
+    stats::Stats,
+    timeline::{Timeline,TimelineSettings},
+    types::{Categories,Grouping,TypeReport},
+    variables::Variables,
     watcher::Watcher
 };
 
+/// Command names, for completion
+pub const COMMANDS : &[&str] = &[
+    "categories","cd","counts","drives","h","he","help","help-expr","label","let",
+    "list","ll","load","longlist","ls","maxb","maxb?","maxbreadth","maxbreadth?","maxd",
+    "maxd?","maxdepth","maxdepth?","maxe","maxe?","maxent","maxent?",
+    "nocounts","nopager","ntr","ntree","pager","pwd","quit","reload",
+    "resolve","stats","timeline","tr","tree","types","unlet","unload",
+    "vars"
+];
+
+/// Commands taking an expression as their argument
+pub const EXPR_COMMANDS : &[&str] = &[
+    "categories","list","ll","longlist","ls","ntr","ntree","stats",
+    "timeline","tr","tree","types"
+];
+
+struct Outcome {
+    res:Result<()>,
+    entries:usize,
+    bytes:u64
+}
+
 pub struct ExaminerCli {
     fss:FileSystems,
     limiter:LimiterSettings,
-    show_counts:bool
+    show_counts:bool,
+    pager:bool,
+    vars:Variables,
+    rc_path:Option<PathBuf>,
+    cwd:Option<(usize,PathBuf)>,
+    catalog:Catalog
 }
 
 
@@ -25,37 +65,370 @@ impl ExaminerCli {
 	Self {
 	    fss,
 	    limiter:LimiterSettings::default(),
-	    show_counts:false
+	    show_counts:false,
+	    pager:true,
+	    vars:Variables::new(),
+	    rc_path:None,
+	    cwd:None,
+	    catalog:Catalog::default()
+	}
+    }
+
+    /// Uses the names of the catalog as labels for the drives loaded
+    /// from its maps
+    pub fn set_catalog(&mut self,catalog:Catalog) {
+	self.catalog = catalog;
+	for idrive in 0..self.fss.systems.len() {
+	    self.name_drive(idrive);
+	}
+    }
+
+    /// Loads the maps of the catalog that are not loaded yet
+    pub fn load_catalog_maps(&mut self) {
+	let paths : Vec<PathBuf> = self.catalog.entries.iter()
+	    .filter(|e| self.fss.find(&e.name).is_err())
+	    .map(|e| self.catalog.map_path(e))
+	    .collect();
+	for path in paths {
+	    if let Err(e) = self.load_map(&path) {
+		warn!("Error loading {:?}: {}",path,e);
+	    }
+	}
+    }
+
+    fn name_drive(&mut self,idrive:usize) {
+	let origin = &self.fss.systems[idrive].origin;
+	if let Some(entry) = self.catalog.find_map(origin) {
+	    if self.fss.find(&entry.name).is_err() {
+		let name = Some(entry.name.clone());
+		let _ = self.fss.set_label(idrive,name);
+	    }
+	}
+    }
+
+    fn load_map(&mut self,path:&Path)->Result<usize> {
+	let idrive = self.fss.load(path)?;
+	self.name_drive(idrive);
+	Ok(idrive)
+    }
+
+    /// Loads variable definitions from path, where they will be saved
+    /// back when modified
+    pub fn load_rc(&mut self,path:PathBuf)->Result<()> {
+	let res =
+	    if path.exists() {
+		self.vars.load(&path)
+	    } else {
+		Ok(())
+	    };
+	self.rc_path = Some(path);
+	res
+    }
+
+    fn save_rc(&self,name:&str)->Result<()> {
+	if let Some(path) = &self.rc_path {
+	    self.vars.save(path,name)?;
 	}
+	Ok(())
     }
 
-    fn process<W:Watcher>(&mut self,w:&str,watcher:W)->Result<W> {
+    fn define(&mut self,w:&str)->Result<()> {
+	let Some((name,source)) = Variables::parse_let(w) else {
+	    bail!("Usage: let NAME = EXPR")
+	};
+	self.vars.define(name,source)?;
+	self.save_rc(name)
+    }
+
+    fn show_vars(&self,out:&mut Output)->Result<()> {
+	for (name,def) in self.vars.iter() {
+	    writeln!(out,"  ${} = {}",name,def.source)?;
+	}
+	Ok(())
+    }
+
+    /// Enables or disables the automatic pager
+    pub fn set_pager(&mut self,pager:bool) {
+	self.pager = pager;
+    }
+
+    pub fn file_systems(&self)->&FileSystems {
+	&self.fss
+    }
+
+    pub fn variables(&self)->&Variables {
+	&self.vars
+    }
+
+    pub fn current_drive(&self)->Option<usize> {
+	self.cwd.as_ref().map(|&(idrive,_)| idrive)
+    }
+
+    /// Parses and expands the expression, as done for searches
+    pub fn parse_expr(&self,w:&str)->Result<FsExpr> {
+	let expr = self.vars.expand(&FsExpr::parse(w)?)?;
+	self.check_labels(&expr)?;
+	Ok(expr)
+    }
+
+    // Rejects references to drive labels that are not defined
+    fn check_labels(&self,expr:&FsExpr)->Result<()> {
+	expr.try_map(&mut |a| {
+	    match a {
+		FsAtom::DriveLabel(l) => { self.fss.find(l)?; },
+		FsAtom::Contains(x) | FsAtom::Under(x) => self.check_labels(x)?,
+		_ => ()
+	    }
+	    Ok::<FsExpr,anyhow::Error>(Expr::True)
+	})?;
+	Ok(())
+    }
+
+    // Runs the search for w, reporting matches to watcher within the
+    // display limits.  The outcome of the traversal is returned
+    // rather than propagated so that counts can be shown once the
+    // watcher, which may be writing to the output, has been dropped.
+    fn process<W:Watcher>(&self,w:&str,watcher:W)->Result<(W,Outcome)> {
+	let lim = Limiter::new(&self.limiter,watcher);
+	let (lim,outcome) = self.process_unlimited(w,lim)?;
+	Ok((lim.into_inner(),outcome))
+    }
+
+    // Same as process, but reporting every match, for aggregates
+    // that the display limits must not truncate
+    fn process_unlimited<W:Watcher>(&self,w:&str,watcher:W)->Result<(W,Outcome)> {
 	let sd = SigintDetector::new();
 	let expr =
 	    if w.is_empty() {
 		FsExpr::True
 	    } else {
-		FsExpr::parse(w)?
+		self.parse_expr(w)?
 	    };
-	let lim = Limiter::new(&self.limiter,watcher);
-	let mut dp = Dumper::new(&sd,&self.fss,&expr,lim);
-	match dp.dump() {
-	    Ok(()) => (),
-	    Err(e) => println!("{}",e)
+	let query = Query::compile(&expr)?;
+	let mut dp = Dumper::new(&sd,&self.fss,&query,watcher);
+	if let Some((idrive,path)) = &self.cwd {
+	    dp.set_scope(*idrive,path.clone());
 	}
+	let res = dp.dump();
+	let outcome = Outcome {
+	    res,
+	    entries:dp.matching_entries,
+	    bytes:dp.matching_bytes
+	};
+	Ok((dp.into_inner(),outcome))
+    }
+
+    fn show_outcome(&self,out:&mut Output,outcome:Outcome)->Result<()> {
 	if self.show_counts {
-	    println!("Entries: {}",dp.matching_entries);
-	    println!("Bytes: {}",dp.matching_bytes);
+	    writeln!(out,"Entries: {}",outcome.entries)?;
+	    writeln!(out,"Bytes: {}",outcome.bytes)?;
+	}
+	outcome.res
+    }
+
+    // Parses a location of the form N:PATH, where N is the drive
+    // number or label.  Without a drive, relative paths are taken from the
+    // current directory, and drive 0 is assumed when it is the only
+    // one.  Returns the drive, the components of the directory the
+    // path is relative to, and the path.
+    pub fn locate<'a>(&'a self,w:&'a str)->Result<(usize,Vec<&'a OsStr>,&'a Path)> {
+	let (idrive,cwd,path) =
+	    match (self.drive_prefix(w)?,&self.cwd) {
+		(Some((idrive,path)),_) => (idrive,None,path),
+		(_,Some((idrive,cwd))) => (*idrive,Some(cwd),w),
+		_ if self.fss.systems.len() == 1 => (0,None,w),
+		_ => bail!("Specify the drive as in N:PATH")
+	    };
+	let cwd =
+	    match (cwd,path.strip_prefix('/')) {
+		(_,Some(path)) => return Ok((idrive,Vec::new(),Path::new(path))),
+		(Some(cwd),None) => cwd.iter().collect(),
+		(None,None) => Vec::new()
+	    };
+	Ok((idrive,cwd,Path::new(path)))
+    }
+
+    // Splits the drive off a location, if it starts with a drive
+    // number or an existing label followed by a colon
+    fn drive_prefix<'a>(&self,w:&'a str)->Result<Option<(usize,&'a str)>> {
+	let Some((d,path)) = w.split_once(':') else { return Ok(None) };
+	if !d.is_empty() && d.chars().all(|c| c.is_ascii_digit()) {
+	    Ok(Some((self.fss.find(d)?,path)))
+	} else {
+	    Ok(self.fss.find(d).ok().map(|idrive| (idrive,path)))
 	}
-	Ok(dp.into_inner().into_inner())
     }
 
-    fn show_limit(&self,d:usize) {
+    fn change_dir(&mut self,w:&str)->Result<()> {
+	if w.is_empty() || (w == ".." && matches!(&self.cwd,Some((_,p)) if p.as_os_str().is_empty())) {
+	    self.cwd = None;
+	    return Ok(());
+	}
+	let (idrive,cwd,path) = self.locate(w)?;
+	let fs = &self.fss.systems[idrive].fs;
+	let cwd =
+	    match Resolver::new(fs).resolve(&cwd,path,true).outcome {
+		Resolution::Found(p,Node::Dir(_)) => p,
+		Resolution::Found(p,Node::Other(_)) =>
+		    bail!("Not a directory: {}:{}",self.fss.drive_name(idrive),p.display()),
+		Resolution::Dangling(p) =>
+		    bail!("No such directory: {}:{}",self.fss.drive_name(idrive),p.display()),
+		Resolution::Outside(p) =>
+		    bail!("Outside of the map: {:?}",p),
+		Resolution::Loop =>
+		    bail!("Too many levels of symbolic links")
+	    };
+	self.cwd = Some((idrive,cwd));
+	Ok(())
+    }
+
+    fn show_cwd(&self,out:&mut Output)->Result<()> {
+	match &self.cwd {
+	    Some((idrive,path)) =>
+		writeln!(out,"{}:/{}",self.fss.drive_name(*idrive),path.display())?,
+	    None => writeln!(out,"(all drives)")?
+	}
+	Ok(())
+    }
+
+    pub fn prompt(&self)->String {
+	match &self.cwd {
+	    Some((idrive,path)) =>
+		format!("{}:/{}> ",self.fss.drive_name(*idrive),path.display()),
+	    None => "> ".to_string()
+	}
+    }
+
+    // Without an expression, list the contents of the current
+    // directory, if any
+    fn listing_expr(&self,w:&str)->String {
+	match &self.cwd {
+	    Some((_,path)) if w.is_empty() =>
+		format!("%depth {}",path.components().count() + 1),
+	    _ => w.to_string()
+	}
+    }
+
+    fn resolve(&self,out:&mut Output,w:&str)->Result<()> {
+	let (idrive,cwd,path) = self.locate(w)?;
+	let fs = &self.fss.systems[idrive].fs;
+	let res = Resolver::new(fs).resolve(&cwd,path,true);
+	let drive = self.fss.drive_name(idrive);
+	for (link,target) in res.hops.iter() {
+	    writeln!(out,"{}:{} -> {:?}",drive,link.display(),target)?;
+	}
+	match res.outcome {
+	    Resolution::Found(p,Node::Dir(dir)) =>
+		writeln!(out,
+			 "{}:{}/ (directory, {} entries)",
+			 drive,
+			 p.display(),
+			 dir.entries.len())?,
+	    Resolution::Found(p,Node::Other(entry)) => {
+		let kind =
+		    match entry {
+			Entry::File(_) => "file",
+			Entry::Archive(..) => "archive",
+			Entry::Symlink(_) => "symlink",
+			Entry::Error(_) => "error",
+			_ => "other"
+		    };
+		writeln!(out,"{}:{} ({})",drive,p.display(),kind)?
+	    },
+	    Resolution::Dangling(p) =>
+		writeln!(out,"Dangling, {}:{} does not exist",drive,p.display())?,
+	    Resolution::Outside(p) =>
+		writeln!(out,"Outside of the map: {:?}",p)?,
+	    Resolution::Loop =>
+		writeln!(out,"Too many levels of symbolic links")?
+	}
+	Ok(())
+    }
+
+    fn show_catalog_entry(&self,out:&mut Output,entry:&CatalogEntry)->Result<()> {
+	for (key,value) in [("Label",&entry.label),
+			    ("Serial",&entry.serial),
+			    ("Description",&entry.description)] {
+	    if let Some(value) = value {
+		writeln!(out,"  {:3} {:16}   {}: {}","","",key,value)?;
+	    }
+	}
+	Ok(())
+    }
+
+    fn load(&mut self,out:&mut Output,w:&str)->Result<()> {
+	if w.trim().is_empty() {
+	    bail!("Usage: load PATH...");
+	}
+	let mut failed = 0;
+	for path in w.split_whitespace() {
+	    // Maps can also be given by their name in the catalog
+	    let path =
+		match self.catalog.get(path) {
+		    Some(entry) if !Path::new(path).exists() => self.catalog.map_path(entry),
+		    _ => PathBuf::from(path)
+		};
+	    match self.load_map(&path) {
+		Ok(idrive) =>
+		    writeln!(out,"Loaded {:?} as drive {}",path,self.fss.drive_name(idrive))?,
+		Err(e) => {
+		    eprintln!("Error loading {:?}: {}",path,e);
+		    failed += 1;
+		}
+	    }
+	}
+	if failed > 0 {
+	    bail!("{} map(s) could not be loaded",failed);
+	}
+	Ok(())
+    }
+
+    // Drives after the unloaded one are renumbered, so the current
+    // directory must follow
+    fn unload(&mut self,w:&str)->Result<()> {
+	let idrive = self.fss.find(w)?;
+	self.fss.unload(idrive);
+	self.cwd =
+	    match self.cwd.take() {
+		Some((i,_)) if i == idrive => None,
+		Some((i,path)) if i > idrive => Some((i - 1,path)),
+		cwd => cwd
+	    };
+	Ok(())
+    }
+
+    fn reload(&mut self,w:&str)->Result<()> {
+	let idrive = self.fss.find(w)?;
+	self.fss.reload(idrive)?;
+	if let Some((i,path)) = &self.cwd {
+	    if *i == idrive {
+		let fs = &self.fss.systems[idrive].fs;
+		let res = Resolver::new(fs).resolve(&[],path,false);
+		if !matches!(res.outcome,Resolution::Found(_,Node::Dir(_))) {
+		    warn!("Current directory no longer exists");
+		    self.cwd = None;
+		}
+	    }
+	}
+	Ok(())
+    }
+
+    fn label(&mut self,w:&str)->Result<()> {
+	let mut words = w.split_whitespace();
+	let (Some(d),label,None) = (words.next(),words.next(),words.next()) else {
+	    bail!("Usage: label N [NAME]")
+	};
+	let idrive = self.fss.find(d)?;
+	self.fss.set_label(idrive,label.map(|l| l.to_string()))
+    }
+
+    fn show_limit(out:&mut Output,d:usize)->Result<()> {
 	if d == usize::MAX {
-	    println!("unlimited");
+	    writeln!(out,"unlimited")?;
 	} else {
-	    println!("{}",d);
+	    writeln!(out,"{}",d)?;
 	}
+	Ok(())
     }
 
     fn set_limit(w:&str,l:&mut usize)->Result<()> {
@@ -68,28 +441,111 @@ impl ExaminerCli {
 	Ok(())
     }
 
+    /// Splits u into commands separated by semicolons outside of
+    /// quoted strings
+    pub fn split_commands(u:&str)->Vec<&str> {
+	let mut res = Vec::new();
+	let mut start = 0;
+	for (i,_) in output::unquoted(u).filter(|&(_,c)| c == ';') {
+	    res.push(&u[start..i]);
+	    start = i + 1;
+	}
+	res.push(&u[start..]);
+	res
+    }
+
+    /// Runs the commands in lines, which may be separated by
+    /// semicolons, skipping blank lines and comments starting with #.
+    /// Returns the number of commands that failed; if strict is set,
+    /// stops at the first failure.
+    pub fn run_batch(&mut self,lines:&[String],strict:bool)->usize {
+	let mut failures = 0;
+	for (iline,line) in lines.iter().enumerate() {
+	    if line.trim_start().starts_with('#') {
+		continue;
+	    }
+	    for cmd in Self::split_commands(line) {
+		match self.handle_input(cmd) {
+		    Ok(true) => return failures,
+		    Ok(false) => (),
+		    Err(e) => {
+			eprintln!("Error at line {}: {}: {}",iline + 1,cmd.trim(),e);
+			failures += 1;
+			if strict {
+			    return failures;
+			}
+		    }
+		}
+	    }
+	}
+	failures
+    }
+
+    /// Runs the command u, whose output may be redirected to a file
+    /// or piped to a command.  Returns true when asked to quit.
     pub fn handle_input(&mut self,u:&str)->Result<bool> {
+	let (u,redirect) = Redirect::parse(u)?;
+	let mut out = Output::open(redirect.as_ref(),self.pager)?;
+	let res = self.run(u,&mut out);
+	let finished = out.finish();
+	match res {
+	    // The pager or command was quit before reading everything
+	    Err(e) if e.downcast_ref::<io::Error>()
+		.is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe) => Ok(false),
+	    res => {
+		finished?;
+		res
+	    }
+	}
+    }
+
+    fn run(&mut self,u:&str,out:&mut Output)->Result<bool> {
 	let u = u.trim();
 	let (v,w) =
 	    u.split_once(' ')
 	    .unwrap_or((u,""));
 	match v {
 	    "list" | "ls" => {
-		let bp = ListPrinter::new(false);
-		let _ = self.process(w,bp)?;
+		let expr = self.listing_expr(w);
+		let (_,outcome) = self.process(&expr,ListPrinter::new(false,&mut *out))?;
+		self.show_outcome(out,outcome)?;
 	    },
 	    "longlist" | "ll" => {
-		let bp = ListPrinter::new(true);
-		let _ = self.process(w,bp)?;
+		let expr = self.listing_expr(w);
+		let (_,outcome) = self.process(&expr,ListPrinter::new(true,&mut *out))?;
+		self.show_outcome(out,outcome)?;
 	    },
 	    "tree" | "tr" => {
-		let bp = BasicPrinter::new();
-		let _ = self.process(w,bp)?;
+		let (_,outcome) = self.process(w,BasicPrinter::new(&mut *out))?;
+		self.show_outcome(out,outcome)?;
 	    },
 	    "ntree" | "ntr" => {
-		let mut bp = BasicPrinter::new();
+		let mut bp = BasicPrinter::new(&mut *out);
 		bp.set_indent_mode(IndentMode::Numbered);
-		let _ = self.process(w,bp)?;
+		let (_,outcome) = self.process(w,bp)?;
+		self.show_outcome(out,outcome)?;
+	    },
+	    "stats" => {
+		let (stats,outcome) = self.process_unlimited(w,Stats::new())?;
+		outcome.res?;
+		stats.write(out)?;
+	    },
+	    "types" | "categories" => {
+		let grouping =
+		    if v == "types" {
+			Grouping::Extension
+		    } else {
+			Grouping::Category(Categories::load(Categories::default_path())?)
+		    };
+		let (report,outcome) = self.process_unlimited(w,TypeReport::new(grouping))?;
+		outcome.res?;
+		report.write(out)?;
+	    },
+	    "timeline" => {
+		let (settings,w) = TimelineSettings::parse(w)?;
+		let (timeline,outcome) = self.process_unlimited(w,Timeline::new(settings))?;
+		outcome.res?;
+		timeline.write(out)?;
 	    },
 	    "maxdepth" | "maxd" =>
 		Self::set_limit(w,&mut self.limiter.max_depth)?,
@@ -98,31 +554,69 @@ impl ExaminerCli {
 	    "maxent" | "maxe" =>
 		Self::set_limit(w,&mut self.limiter.max_entries)?,
 	    "drives" => {
-		println!("Drives:");
-		for (idrive,FileSystemEntry { origin,.. }) in
+		writeln!(out,"Drives:")?;
+		for (idrive,FileSystemEntry { origin,label,.. }) in
 		    self.fss.systems.iter().enumerate() {
-			println!("  {:3} {:?}",
+			writeln!(out,
+				 "  {:3} {:16} {:?}",
 				 idrive,
-				 origin);
+				 label.as_deref().unwrap_or(""),
+				 origin)?;
+			if let Some(entry) = label.as_ref().and_then(|l| self.catalog.get(l)) {
+			    self.show_catalog_entry(out,entry)?;
+			}
 		    }
 	    },
+	    "load" => self.load(out,w)?,
+	    "unload" => self.unload(w.trim())?,
+	    "reload" => self.reload(w.trim())?,
+	    "label" => self.label(w)?,
+	    "resolve" => self.resolve(out,w)?,
+	    "cd" => self.change_dir(w.trim())?,
+	    "pwd" => self.show_cwd(out)?,
+	    "let" => self.define(w)?,
+	    "unlet" => {
+		self.vars.undefine(w.trim())?;
+		self.save_rc(w.trim())?;
+	    },
+	    "vars" => self.show_vars(out)?,
 	    "counts" => {
 		self.show_counts = true;
-		println!("Will show counts");
+		writeln!(out,"Will show counts")?;
 	    },
 	    "nocounts" => {
 		self.show_counts = false;
-		println!("Won't show counts");
+		writeln!(out,"Won't show counts")?;
+	    },
+	    "pager" => {
+		self.pager = true;
+		writeln!(out,"Will page long output")?;
+	    },
+	    "nopager" => {
+		self.pager = false;
+		writeln!(out,"Won't page long output")?;
 	    },
-	    "maxdepth?" | "maxd?" => self.show_limit(self.limiter.max_depth),
-	    "maxbreadth?" | "maxb?" => self.show_limit(self.limiter.max_breadth),
-	    "maxent?" | "maxe?" => self.show_limit(self.limiter.max_entries),
+	    "maxdepth?" | "maxd?" => Self::show_limit(out,self.limiter.max_depth)?,
+	    "maxbreadth?" | "maxb?" => Self::show_limit(out,self.limiter.max_breadth)?,
+	    "maxent?" | "maxe?" => Self::show_limit(out,self.limiter.max_entries)?,
 	    "quit" => return Ok(true),
-	    "help" | "h" => print!("{}",help::CLI_TEXT),
-	    "help-expr" | "he" => print!("{}",help::EXPR_TEXT),
+	    "help" | "h" => write!(out,"{}",help::CLI_TEXT)?,
+	    "help-expr" | "he" => write!(out,"{}",help::EXPR_TEXT)?,
 	    "" => (),
 	    _ => bail!("Unknown command")
 	}
 	Ok(false)
     }
 }
+
+#[test]
+fn test_split_commands() {
+    for (u,v) in &[
+	("maxd 3; counts;ll",&["maxd 3"," counts","ll"][..]),
+	("ls 'a;b' | c; ll",&["ls 'a;b' | c"," ll"][..]),
+	("ls 'it\\'s;' ;",&["ls 'it\\'s;' ",""][..]),
+	("ls",&["ls"][..])
+    ] {
+	assert_eq!(&ExaminerCli::split_commands(u)[..],*v);
+    }
+}
@@ -0,0 +1,236 @@
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::path::Path;
+use rustyline::{
+    self as rl,
+    completion::{Completer,FilenameCompleter,Pair},
+    highlight::Highlighter,
+    hint::Hinter,
+    validate::Validator,
+    Context,
+    Helper
+};
+
+use crate::{
+    examiner_cli::{COMMANDS,EXPR_COMMANDS,ExaminerCli},
+    fsmap::*,
+    fstok::KEYWORDS,
+    resolver::{Node,Resolution,Resolver}
+};
+
+/// Rustyline helper providing completion, and flagging invalid
+/// expressions as they are typed
+pub struct ExaminerHelper {
+    pub cli:ExaminerCli,
+    // Map files for load
+    files:FilenameCompleter
+}
+
+impl ExaminerHelper {
+    pub fn new(cli:ExaminerCli)->Self {
+	Self { cli,files:FilenameCompleter::new() }
+    }
+
+    fn is_separator(c:char)->bool {
+	c.is_whitespace() || matches!(c,'(' | ')' | '&' | '|' | '\\')
+    }
+
+    // Returns the command and its argument if the command takes an
+    // expression
+    fn expr_of(line:&str)->Option<(&str,&str)> {
+	let line = line.trim_start();
+	let (cmd,w) = line.split_once(' ')?;
+	if EXPR_COMMANDS.contains(&cmd) && !w.trim().is_empty() {
+	    Some((cmd,w))
+	} else {
+	    None
+	}
+    }
+
+    fn check_expr(&self,line:&str)->Option<String> {
+	let (_,w) = Self::expr_of(line)?;
+	self.cli.parse_expr(w).err().map(|e| e.to_string())
+    }
+
+    fn candidates<'a,I>(start:&str,names:I)->Vec<Pair>
+    where I:Iterator<Item=&'a str> {
+	let mut res : Vec<Pair> = names
+	    .filter(|u| u.starts_with(start))
+	    .map(|u| Pair { display:u.to_string(),replacement:u.to_string() })
+	    .collect();
+	res.sort_by(|a,b| a.display.cmp(&b.display));
+	res.dedup_by(|a,b| a.display == b.display);
+	res
+    }
+
+    // Completes the names of the entries of dir, relative to cwd on
+    // the given drive, that start with partial
+    fn complete_in(&self,
+		   idrive:usize,
+		   cwd:&[&OsStr],
+		   dir:&Path,
+		   head:&str,
+		   partial:&str,
+		   dirs_only:bool)->Vec<Pair> {
+	let mut res = Vec::new();
+	let fs = &self.cli.file_systems().systems[idrive].fs;
+	let Resolution::Found(_,Node::Dir(d)) =
+	    Resolver::new(fs).resolve(cwd,dir,true).outcome else { return res };
+	for (name,entry) in d.entries.iter() {
+	    let name = name.to_string_lossy();
+	    if !name.starts_with(partial) {
+		continue;
+	    }
+	    let suffix =
+		match entry {
+		    Entry::Dir(_) => "/",
+		    Entry::Symlink(_) => "",
+		    _ if dirs_only => continue,
+		    _ => ""
+		};
+	    res.push(Pair {
+		display:format!("{}{}",name,suffix),
+		replacement:format!("{}{}{}",head,name,suffix)
+	    });
+	}
+	res
+    }
+
+    // Splits a path being typed into its directory part, including
+    // any drive prefix and the trailing slash, and the partial name
+    fn split_path(word:&str)->(&str,&str) {
+	match word.rfind('/') {
+	    Some(i) => word.split_at(i + 1),
+	    None =>
+		match word.split_once(':') {
+		    Some((d,_)) => word.split_at(d.len() + 1),
+		    _ => ("",word)
+		}
+	}
+    }
+
+    fn complete_location(&self,word:&str,dirs_only:bool)->Vec<Pair> {
+	let (head,partial) = Self::split_path(word);
+	let mut res =
+	    match self.cli.locate(head) {
+		Ok((idrive,cwd,dir)) =>
+		    self.complete_in(idrive,&cwd,dir,head,partial,dirs_only),
+		Err(_) => Vec::new()
+	    };
+	res.sort_by(|a,b| a.display.cmp(&b.display));
+	res
+    }
+
+    // Paths in expressions are regular expressions matched from the
+    // root of each drive
+    fn complete_expr_path(&self,word:&str)->Vec<Pair> {
+	let anchor = if word.starts_with('^') { "^" } else { "" };
+	let (head,partial) = Self::split_path(&word[anchor.len()..]);
+	let prefix = format!("{}{}",anchor,head);
+	let drives : Vec<usize> =
+	    match self.cli.current_drive() {
+		Some(idrive) => vec![idrive],
+		None => (0..self.cli.file_systems().systems.len()).collect()
+	    };
+	let mut res = Vec::new();
+	for idrive in drives {
+	    res.extend(
+		self.complete_in(idrive,&[],Path::new(head),&prefix,partial,false));
+	}
+	res.sort_by(|a,b| a.display.cmp(&b.display));
+	res.dedup_by(|a,b| a.display == b.display);
+	res
+    }
+}
+
+impl Completer for ExaminerHelper {
+    type Candidate = Pair;
+
+    fn complete(&self,line:&str,pos:usize,ctx:&Context<'_>)
+		->rl::Result<(usize,Vec<Pair>)> {
+	let before = &line[..pos];
+	let start = before
+	    .rfind(Self::is_separator)
+	    .map(|i| i + before[i..].chars().next().map(|c| c.len_utf8()).unwrap_or(1))
+	    .unwrap_or(0);
+	let word = &before[start..];
+	let Some(cmd) = before[..start].split_whitespace().next() else {
+	    return Ok((start,Self::candidates(word,COMMANDS.iter().copied())));
+	};
+	let prev = before[..start].split_whitespace().last().unwrap_or("");
+
+	let res =
+	    if cmd == "load" {
+		return self.files.complete(line,pos,ctx);
+	    } else if cmd == "cd" || cmd == "resolve" {
+		// Locations may contain separators such as spaces
+		let arg = before.trim_start()[cmd.len()..].trim_start();
+		let start = pos - arg.len();
+		return Ok((start,self.complete_location(arg,cmd == "cd")));
+	    } else if !EXPR_COMMANDS.contains(&cmd) && cmd != "let" {
+		Vec::new()
+	    } else if prev == "%drive" {
+		let fss = self.cli.file_systems();
+		let drives : Vec<String> =
+		    (0..fss.systems.len()).map(|i| i.to_string()).collect();
+		let labels = fss.systems.iter().filter_map(|fse| fse.label.as_deref());
+		Self::candidates(word,drives.iter().map(|u| u.as_str()).chain(labels))
+	    } else if let Some(kw) = word.strip_prefix('%') {
+		Self::candidates(kw,KEYWORDS.iter().map(|(k,_)| *k))
+		    .into_iter()
+		    .map(|p| Pair {
+			display:format!("%{}",p.display),
+			replacement:format!("%{}",p.replacement)
+		    })
+		    .collect()
+	    } else if let Some(v) = word.strip_prefix('$') {
+		Self::candidates(v,self.cli.variables().iter().map(|(n,_)| n.as_str()))
+		    .into_iter()
+		    .map(|p| Pair {
+			display:format!("${}",p.display),
+			replacement:format!("${}",p.replacement)
+		    })
+		    .collect()
+	    } else if word.contains('/') {
+		self.complete_expr_path(word)
+	    } else {
+		Vec::new()
+	    };
+	Ok((start,res))
+    }
+}
+
+impl Hinter for ExaminerHelper {
+    type Hint = String;
+
+    fn hint(&self,line:&str,pos:usize,_ctx:&Context<'_>)->Option<String> {
+	if pos < line.len() {
+	    return None;
+	}
+	self.check_expr(line).map(|e| format!("  <- {}",e))
+    }
+}
+
+impl Highlighter for ExaminerHelper {
+    fn highlight<'l>(&self,line:&'l str,_pos:usize)->Cow<'l,str> {
+	match Self::expr_of(line) {
+	    Some((_,w)) if self.check_expr(line).is_some() => {
+		let i = line.len() - w.len();
+		Cow::Owned(format!("{}\x1b[31m{}\x1b[0m",&line[..i],w))
+	    },
+	    _ => Cow::Borrowed(line)
+	}
+    }
+
+    fn highlight_hint<'h>(&self,hint:&'h str)->Cow<'h,str> {
+	Cow::Owned(format!("\x1b[2m{}\x1b[0m",hint))
+    }
+
+    fn highlight_char(&self,_line:&str,_pos:usize,_forced:bool)->bool {
+	true
+    }
+}
+
+impl Validator for ExaminerHelper { }
+
+impl Helper for ExaminerHelper { }
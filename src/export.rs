@@ -0,0 +1,63 @@
+use std::fs::File;
+use std::io::{BufWriter,Write};
+use std::path::Path;
+use anyhow::{bail,Result};
+
+use crate::{
+    fsmap::FileSystems,
+    mtree,
+    ncdu,
+    sqlite
+};
+
+/// Formats maps can be converted to for other tools
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Format {
+    // JSON export of ncdu, for ncdu -f
+    Ncdu,
+    // BSD mtree specification
+    Mtree,
+    // SQLite database with tables of drives, devices, inodes and
+    // entries
+    Sqlite
+}
+
+impl Format {
+    pub fn parse(u:&str)->Result<Self> {
+	match u {
+	    "ncdu" => Ok(Self::Ncdu),
+	    "mtree" => Ok(Self::Mtree),
+	    "sqlite" => Ok(Self::Sqlite),
+	    _ => bail!("Unknown format {:?}, expected ncdu, mtree or sqlite",u)
+	}
+    }
+}
+
+fn writer(out:Option<&Path>)->Result<Box<dyn Write>> {
+    Ok(match out {
+	Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+	None => Box::new(BufWriter::new(std::io::stdout().lock()))
+    })
+}
+
+/// Writes the maps in the given format into the file out, or on
+/// standard output for text formats
+pub fn export(fss:&FileSystems,format:Format,out:Option<&Path>)->Result<()> {
+    match format {
+	Format::Ncdu | Format::Mtree => {
+	    let [fse] = &fss.systems[..] else { bail!("The {:?} format holds a single map",format) };
+	    let mut w = writer(out)?;
+	    if format == Format::Ncdu {
+		ncdu::export(&fse.fs,&mut w)?;
+	    } else {
+		mtree::export(&fse.fs,&mut w)?;
+	    }
+	    w.flush()?;
+	},
+	Format::Sqlite => {
+	    let Some(path) = out else { bail!("Give the database to write with --out") };
+	    sqlite::export(fss,path)?;
+	}
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::path::{Component,Path};
+use anyhow::{bail,Result};
+
+use crate::{
+    fsexpr::FsData,
+    fsmap::*,
+    watcher::{Action,Watcher}
+};
+
+/// Watcher copying the matching entries and their ancestor
+/// directories into a new tree
+pub struct Extractor {
+    // Number of ancestors above the new root
+    skip:usize,
+    root:Directory,
+    inodes:BTreeSet<(u64,u64)>,
+    devices:BTreeSet<u64>
+}
+
+/// Finds the directory at path in fs, which must not go through
+/// symbolic links
+pub fn find_dir<'a>(fs:&'a FileSystem,path:&Path)->Result<(&'a Directory,usize)> {
+    let mut dir = &fs.root;
+    let mut depth = 0;
+    for c in path.components() {
+	let Component::Normal(name) = c else { continue };
+	match dir.entries.iter().find(|(n,_)| n == name) {
+	    Some((_,Entry::Dir(sub))) => dir = sub,
+	    Some(_) => bail!("Not a directory: {:?}",name),
+	    None => bail!("Cannot find {:?}",name)
+	}
+	depth += 1;
+    }
+    Ok((dir,depth))
+}
+
+impl Extractor {
+    /// The new root is the directory at depth skip, on device dev
+    pub fn new(skip:usize,dev:u64)->Self {
+	Self {
+	    skip,
+	    root:Directory::new(dev),
+	    inodes:BTreeSet::new(),
+	    devices:BTreeSet::from([dev])
+	}
+    }
+
+    /// Builds the extracted map, keeping only the inodes of src that
+    /// are referenced
+    pub fn finish(self,src:&FileSystem,base:Option<OsString>)->FileSystem {
+	let mut mounts = Mounts::new();
+	for &dev in self.devices.iter() {
+	    mounts.ensure_device(dev);
+	}
+	for &(dev,ino) in self.inodes.iter() {
+	    if let Some(fi) = src.mounts.get_device(dev).and_then(|d| d.get_inode(ino)) {
+		mounts.get_device_mut(dev).unwrap().insert_inode(ino,fi.clone());
+	    }
+	}
+	FileSystem { mounts,root:self.root,base }
+    }
+
+    fn copy(&mut self,entry:&Entry,dev:u64)->Entry {
+	match entry {
+	    Entry::Dir(dir) => {
+		self.devices.insert(dir.dev);
+		Entry::Dir(Directory::new(dir.dev))
+	    },
+	    &Entry::File(ino) => {
+		self.inodes.insert((dev,ino));
+		Entry::File(ino)
+	    },
+	    &Entry::Other(ino) => {
+		self.inodes.insert((dev,ino));
+		Entry::Other(ino)
+	    },
+	    Entry::Symlink(target) => Entry::Symlink(target.clone()),
+	    Entry::Error(e) => Entry::Error(e.clone()),
+	    &Entry::Archive(ino,ref dir) => {
+		self.inodes.insert((dev,ino));
+		self.devices.insert(dir.dev);
+		Entry::Archive(ino,Directory::new(dir.dev))
+	    }
+	}
+    }
+}
+
+// Entries are visited depth first, so the directory holding an entry
+// is the last one of its parent if it was already copied
+fn subdir<'a>(dir:&'a mut Directory,name:&OsString,copy:Entry)->&'a mut Directory {
+    let found = matches!(dir.entries.last(),Some((n,e)) if n == name && e.as_dir().is_some());
+    if !found {
+	dir.insert(name.clone(),copy);
+    }
+    match dir.entries.last_mut().and_then(|(_,e)| e.as_dir_mut()) {
+	Some(sub) => sub,
+	None => unreachable!()
+    }
+}
+
+impl Watcher for Extractor {
+    fn matching_entry(&mut self,
+		      _fse:&FileSystemEntry,
+		      name:&OsString,
+		      _device:&Device,
+		      entry:&Entry,
+		      data:&FsData)->Result<Action> {
+	if data.depth <= self.skip {
+	    return Ok(Action::Enter);
+	}
+	let mut ancestors : Vec<FsData> = data.ancestors()
+	    .filter(|a| a.depth > self.skip)
+	    .collect();
+	ancestors.reverse();
+	let copies : Vec<(OsString,Entry)> = ancestors.iter()
+	    .map(|a| (a.raw_name.to_os_string(),self.copy(a.entry,a.dev())))
+	    .collect();
+	let copy = self.copy(entry,data.dev());
+	let mut dir = &mut self.root;
+	for (name,ancestor) in copies {
+	    dir = subdir(dir,&name,ancestor);
+	}
+	dir.insert(name.clone(),copy);
+	Ok(Action::Enter)
+    }
+}
+
+#[test]
+fn test_extract() {
+    use std::path::PathBuf;
+    use crate::{dumper::{Dumper,test_maps},fsexpr::FsExpr,query::Query,sigint_detector::SigintDetector};
+    fn paths(dir:&Directory,prefix:&str,out:&mut Vec<String>) {
+	for (name,entry) in dir.entries.iter() {
+	    let path = format!("{}{}",prefix,name.to_string_lossy());
+	    out.push(path.clone());
+	    if let Some(sub) = entry.as_dir() {
+		paths(sub,&format!("{}/",path),out);
+	    }
+	}
+    }
+    let fss = test_maps("d 0 r
+d 0 r/p
+f 1 r/p/x.jpg
+f 2 r/p/y.txt
+d 0 r/q
+f 3 r/q/z.jpg
+f 4 r/q/w.txt
+f 5 r/top.jpg
+f 6 u.jpg");
+    let fs = &fss.systems[0].fs;
+    let extract = |expr:&str,root:&str| {
+	let query = Query::compile(&FsExpr::parse(expr).unwrap()).unwrap();
+	let root = PathBuf::from(root);
+	let (dir,depth) = find_dir(fs,&root).unwrap();
+	let sd = SigintDetector::new();
+	let mut dp = Dumper::new(&sd,&fss,&query,Extractor::new(depth,dir.dev));
+	if depth > 0 {
+	    dp.set_scope(0,root);
+	}
+	dp.dump().unwrap();
+	let out = dp.into_inner().finish(fs,None);
+	let mut list = Vec::new();
+	paths(&out.root,"",&mut list);
+	let inodes : usize = out.mounts.devices.values().map(|d| d.inodes.len()).sum();
+	(list,inodes)
+    };
+    let (list,inodes) = extract("jpg$","");
+    assert_eq!(list,["r","r/p","r/p/x.jpg","r/q","r/q/z.jpg","r/top.jpg","u.jpg"]);
+    assert_eq!(inodes,4);
+    // Directories matching along with their contents
+    let (list,inodes) = extract("jpg$ | %name ^q$","r");
+    assert_eq!(list,["p","p/x.jpg","q","q/z.jpg","top.jpg"]);
+    assert_eq!(inodes,3);
+    let (list,inodes) = extract("%t","r/q");
+    assert_eq!(list,["w.txt","z.jpg"]);
+    assert_eq!(inodes,2);
+}
@@ -1,8 +1,14 @@
 use anyhow::Result;
+use std::ffi::OsStr;
 use tz::UtcDateTime;
 use regex::Regex;
+use log::warn;
 
-use crate::boolean::Expr;
+use crate::{
+    boolean::Expr,
+    fsmap::*,
+    resolver::Resolver
+};
 
 #[derive(Copy,Clone,Debug)]
 pub struct FsDate {
@@ -14,25 +20,54 @@ pub struct FsDate {
 #[derive(Clone,Debug)]
 pub enum FsAtom {
     Drive(u64),
+    DriveLabel(String),
     PathMatch(Regex),
     NameMatch(Regex),
+    TargetMatch(Regex),
     Before(i64),
     After(i64),
     Smaller(u64),
-    Larger(u64)
+    Larger(u64),
+    Size(u64,u64),
+    Depth(usize),
+    Empty,
+    Dangling,
+    Contains(Box<FsExpr>),
+    Under(Box<FsExpr>),
+    Var(String)
 }
 
 pub type FsExpr = Expr<FsAtom>;
 
-pub struct FsDataGen<T> {
+pub struct FsData<'a> {
     // Drive ID
     pub drive:u64,
-    
+
     // Base name
-    pub name:T,
-    
+    pub name:&'a str,
+    pub raw_name:&'a OsStr,
+
     // Full path
-    pub path:T,
+    pub path:&'a str,
+
+    // Length of the path, which for the directories above an entry
+    // is kept only as a prefix of the path of that entry
+    path_len:usize,
+
+    // Number of path components below the root
+    pub depth:usize,
+
+    // The entry itself, and the file system holding it
+    pub entry:&'a Entry,
+    pub fse:&'a FileSystemEntry,
+
+    // Resolver of symbolic links in that file system, shared by all
+    // its entries
+    pub resolver:&'a Resolver<'a>,
+
+    // Containing directory, if not at the root, whose path must be
+    // obtained through ancestors()
+    parent:Option<&'a FsData<'a>>,
 
     // Timestamp (Unix)
     pub timestamp:Option<i64>,
@@ -56,36 +91,164 @@ impl FsDate {
 }
 
 
-impl<T> FsDataGen<T> {
-    #[allow(dead_code)]
-    pub fn map<U,F:Fn(&T)->U>(&self,f:F)->FsDataGen<U> {
-	let &Self { drive,ref name,ref path,timestamp,size } = self;
-	FsDataGen {
-	    name:f(name),
-	    path:f(path),
+impl<'a> FsData<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(drive:u64,
+	       name:&'a str,
+	       raw_name:&'a OsStr,
+	       path:&'a str,
+	       depth:usize,
+	       entry:&'a Entry,
+	       device:&'a Device,
+	       fse:&'a FileSystemEntry,
+	       resolver:&'a Resolver<'a>,
+	       parent:Option<&'a FsData<'a>>)->Self {
+	let mut data = Self {
 	    drive,
-	    timestamp,
-	    size
+	    name,
+	    raw_name,
+	    path,
+	    path_len:path.len(),
+	    depth,
+	    entry,
+	    fse,
+	    resolver,
+	    parent,
+	    timestamp:None,
+	    size:None
+	};
+	match entry {
+	    &Entry::File(ino) | &Entry::Archive(ino,_) => {
+		if let Some(fi) = device.get_inode(ino) {
+		    data.size = Some(fi.size);
+		    data.timestamp = Some(fi.unix_time());
+		} else {
+		    warn!("Inode {} not found",ino);
+		}
+	    },
+	    Entry::Dir(_) |
+	    Entry::Symlink(_) |
+	    Entry::Other(_) |
+	    Entry::Error(_) => ()
+	}
+	data
+    }
+
+    /// The same entry as the parent of the entries below it, without
+    /// its path, so that the buffer holding their paths can grow while
+    /// it is borrowed.  path_len is the length of its path, which
+    /// starts theirs.
+    pub fn into_parent(self,path_len:usize)->Self {
+	Self { path:"",path_len,..self }
+    }
+
+    /// The same entry at the given path, which must have the length
+    /// recorded by into_parent()
+    pub fn with_path<'b>(&self,path:&'b str)->FsData<'b> where 'a:'b {
+	FsData { path,..*self }
+    }
+
+    /// Separator between the path of this entry and the names of its
+    /// children.  Members of archives are separated from the archive
+    /// by a double slash.
+    pub fn separator(&self)->&'static str {
+	match self.entry {
+	    Entry::Archive(..) => "//",
+	    _ => "/"
 	}
     }
-}
 
-pub type FsData<'a> = FsDataGen<&'a str>;
+    /// Calls f on the data of every child of this entry, stopping
+    /// as soon as f returns true
+    pub fn any_child<F:Fn(&FsData)->bool>(&self,f:F)->bool {
+	let Some(dir) = self.entry.as_dir() else { return false };
+	let Some(device) = self.fse.fs.mounts.get_device(dir.dev) else {
+	    return false
+	};
+	let mut path = String::from(self.path);
+	path.push_str(self.separator());
+	let len = path.len();
+	dir.entries.iter().any(|(name,entry)| {
+	    let nsl = name.to_string_lossy();
+	    path.truncate(len);
+	    path.push_str(&nsl);
+	    let data = FsData::new(
+		self.drive,
+		&nsl,
+		name,
+		&path,
+		self.depth + 1,
+		entry,
+		device,
+		self.fse,
+		self.resolver,
+		Some(self));
+	    f(&data)
+	})
+    }
+
+    /// Device holding the inode of the entry, that of its directory
+    pub fn dev(&self)->u64 {
+	match self.parent.and_then(|p| p.entry.as_dir()) {
+	    Some(dir) => dir.dev,
+	    None => self.fse.fs.root.dev
+	}
+    }
+
+    /// Data of the directories above this entry, innermost first,
+    /// with their paths taken from that of this entry
+    pub fn ancestors(&self)->impl Iterator<Item=FsData<'_>> {
+	std::iter::successors(self.parent,|d| d.parent)
+	    .map(|d| FsData { path:&self.path[..d.path_len],..*d })
+    }
+
+    pub fn is_dangling(&self)->bool {
+	self.resolver
+	    .resolve_link(self)
+	    .map(|r| r.outcome.is_dangling())
+	    .unwrap_or(false)
+    }
+
+    pub fn is_empty(&self)->bool {
+	match self.entry {
+	    Entry::Dir(dir) => dir.entries.is_empty(),
+	    Entry::File(_) | Entry::Archive(..) => self.size == Some(0),
+	    _ => false
+	}
+    }
+}
 
 pub trait Predicate {
     fn test(&self,data:&FsData)->bool;
+
+    /// Returns false if no entry below the directory described by
+    /// data can match, allowing the whole subtree to be skipped
+    fn may_match_below(&self,_data:&FsData)->bool { true }
 }
 
 impl FsAtom {
     pub fn eval(&self,data:&FsData)->bool {
 	match self {
 	    &Self::Drive(x) => data.drive == x,
+	    Self::DriveLabel(l) => data.fse.label.as_ref() == Some(l),
 	    Self::PathMatch(rx) => rx.is_match(data.path),
 	    Self::NameMatch(rx) => rx.is_match(data.name),
+	    Self::TargetMatch(rx) =>
+		match data.entry {
+		    Entry::Symlink(target) => rx.is_match(&target.to_string_lossy()),
+		    _ => false
+		},
 	    &Self::Smaller(x) => data.size.map(|s| s <= x).unwrap_or(false),
 	    &Self::Larger(x) => data.size.map(|s| x <= s).unwrap_or(false),
+	    &Self::Size(x,y) => data.size.map(|s| x <= s && s <= y).unwrap_or(false),
 	    &Self::Before(x) => data.timestamp.map(|t| t <= x).unwrap_or(false),
 	    &Self::After(x) => data.timestamp.map(|t| x <= t).unwrap_or(false),
+	    &Self::Depth(x) => data.depth <= x,
+	    Self::Empty => data.is_empty(),
+	    Self::Dangling => data.is_dangling(),
+	    Self::Contains(e) => data.any_child(|d| e.test(d)),
+	    Self::Under(e) => data.ancestors().any(|d| e.test(&d)),
+	    Self::Var(_) => false
 	}
     }
 }
@@ -95,3 +258,27 @@ impl Predicate for Expr<FsAtom> {
 	self.eval(&|atom:&FsAtom| atom.eval(data))
     }
 }
+
+#[test]
+fn test_eval() {
+    use crate::dumper::{matching_paths,test_maps};
+    let fss = test_maps("d 0 a
+d 0 a/b
+f 3 a/b/y.rs
+f 0 a/empty
+f 5 a/x.txt
+d 0 c
+d 0 c/node_modules
+f 1 c/node_modules/m.js
+f 2 c/z.txt
+d 0 e");
+    let paths = |u:&str| matching_paths(&fss,&FsExpr::parse(u).unwrap());
+    assert_eq!(paths("%depth 1"),["a","c","e"]);
+    assert_eq!(paths("%depth 2 \\ %depth 1"),["a/b","a/empty","a/x.txt","c/node_modules","c/z.txt"]);
+    assert_eq!(paths("%empty"),["a/empty","e"]);
+    assert_eq!(paths("%contains %name '^y\\.rs$'"),["a/b"]);
+    assert_eq!(paths("%contains %name 'txt$' \\ %contains %name ^node_modules$"),["a"]);
+    assert_eq!(paths("%under %name ^c$ \\ %under %name ^node_modules$"),["c/node_modules","c/z.txt"]);
+    assert_eq!(paths("(rs$ | js$) \\ %under %name ^node_modules$"),["a/b/y.rs"]);
+    assert_eq!(paths("%contains %empty \\ %under %t"),["a"]);
+}
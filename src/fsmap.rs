@@ -1,4 +1,4 @@
-use anyhow::{Error,Result};
+use anyhow::{bail,Error,Result};
 use serde::{Serialize,Deserialize};
 use std::collections::BTreeMap;
 use std::ffi::OsString;
@@ -8,6 +8,8 @@ use std::os::unix::fs::MetadataExt;
 use std::path::Path;
 use log::{self,error,info};
 
+use crate::checksum::Digest;
+
 #[derive(Debug,Serialize,Deserialize)]
 pub struct Directory {
     pub dev:u64,
@@ -20,7 +22,9 @@ pub enum Entry {
     File(u64),
     Symlink(OsString),
     Other(u64),
-    Error(String)
+    Error(String),
+    // Archive file, with the members listed by collect --descend-archives
+    Archive(u64,Directory)
 }
 
 #[derive(Debug,Serialize,Deserialize)]
@@ -36,13 +40,18 @@ pub struct Mounts {
 #[derive(Debug,Serialize,Deserialize)]
 pub struct FileSystem {
     pub mounts:Mounts,
-    pub root:Directory
+    pub root:Directory,
+    // Absolute path of the scanned directory, absent from older maps
+    #[serde(default)]
+    pub base:Option<OsString>
 }
 
 #[derive(Debug)]
 pub struct FileSystemEntry {
     pub origin:OsString,
-    pub fs:FileSystem
+    pub fs:FileSystem,
+    // Name given to the drive in examine
+    pub label:Option<String>
 }
 
 #[derive(Debug)]
@@ -50,10 +59,18 @@ pub struct FileSystems {
     pub systems:Vec<FileSystemEntry>
 }
 
-#[derive(Debug,Serialize,Deserialize)]
+#[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct FileInfo {
     pub size:u64,
-    pub time:i32
+    pub time:i32,
+    // SHA-256 of the contents, only computed by collect --digest.
+    // Always written, so that mtime cannot be read in its place.
+    #[serde(default)]
+    pub digest:Option<Digest>,
+    // Modification time in seconds, which unlike time is not changed
+    // by reading the file; not recorded by older versions
+    #[serde(default,skip_serializing_if="Option::is_none")]
+    pub mtime:Option<i64>
 }
 
 impl FileInfo {
@@ -61,7 +78,9 @@ impl FileInfo {
 	let time = (md.mtime().max(md.atime()).max(md.ctime()) / 60) as i32;
 	Self {
 	    size:md.size(),
-	    time
+	    time,
+	    digest:None,
+	    mtime:Some(md.mtime())
 	}
     }
 
@@ -98,6 +117,34 @@ impl Directory {
     }
 }
 
+impl Entry {
+    /// The entries below this one, for directories and archives
+    pub fn as_dir(&self)->Option<&Directory> {
+	match self {
+	    Self::Dir(dir) | Self::Archive(_,dir) => Some(dir),
+	    _ => None
+	}
+    }
+
+    pub fn as_dir_mut(&mut self)->Option<&mut Directory> {
+	match self {
+	    Self::Dir(dir) | Self::Archive(_,dir) => Some(dir),
+	    _ => None
+	}
+    }
+
+    /// Name of the kind of file, archives being files
+    pub fn kind_name(&self)->&'static str {
+	match self {
+	    Self::Dir(_) => "dir",
+	    Self::File(_) | Self::Archive(..) => "file",
+	    Self::Symlink(_) => "link",
+	    Self::Other(_) => "other",
+	    Self::Error(_) => "error"
+	}
+    }
+}
+
 impl Mounts {
     pub fn new()->Self {
 	Self{ devices:BTreeMap::new() }
@@ -135,6 +182,32 @@ impl FileSystem {
         self.serialize(&mut rmp_serde::Serializer::new(&mut buf))?;
         Ok(())
     }
+
+    /// Whether collect --digest stored the digests of files
+    pub fn has_digests(&self)->bool {
+	self.mounts.devices.values()
+	    .any(|d| d.inodes.values().any(|fi| fi.digest.is_some()))
+    }
+}
+
+impl FileSystemEntry {
+    pub fn load<P:AsRef<Path>>(path:P)->Result<Self> {
+	let fs = FileSystem::from_file(&path)?;
+	Ok(Self {
+	    origin:path.as_ref().as_os_str().to_os_string(),
+	    fs,
+	    label:None
+	})
+    }
+
+    /// Labels start with a letter and may contain letters, digits,
+    /// dashes, dots and underscores, so that they can be written
+    /// unquoted in expressions
+    pub fn is_valid_label(label:&str)->bool {
+	let mut chars = label.chars();
+	chars.next().is_some_and(|c| c.is_ascii_alphabetic()) &&
+	    chars.all(|c| c.is_ascii_alphanumeric() || matches!(c,'-' | '.' | '_'))
+    }
 }
 
 impl FileSystems {
@@ -144,8 +217,8 @@ impl FileSystems {
 	let mut errors = Vec::new();
 	for p in paths.iter() {
 	    let name = p.as_ref().as_os_str().to_os_string();
-	    match FileSystem::from_file(p) {
-		Ok(fs) => systems.push(FileSystemEntry { origin:name,fs }),
+	    match FileSystemEntry::load(p) {
+		Ok(fse) => systems.push(fse),
 		Err(e) => {
 		    error!("Error loading {:?}: {}",name,e);
 		    errors.push((name,e));
@@ -154,4 +227,62 @@ impl FileSystems {
 	}
 	(Self { systems },errors)
     }
+
+    /// Finds a drive by number or label
+    pub fn find(&self,u:&str)->Result<usize> {
+	let idrive =
+	    if u.chars().all(|c| c.is_ascii_digit()) {
+		u.parse()?
+	    } else {
+		match self.systems.iter().position(|fse| fse.label.as_deref() == Some(u)) {
+		    Some(idrive) => idrive,
+		    None => bail!("No drive labelled {:?}",u)
+		}
+	    };
+	if idrive >= self.systems.len() {
+	    bail!("No drive {}",idrive);
+	}
+	Ok(idrive)
+    }
+
+    /// Name of the drive for display, its label if it has one
+    pub fn drive_name(&self,idrive:usize)->String {
+	match self.systems.get(idrive).and_then(|fse| fse.label.as_ref()) {
+	    Some(label) => label.clone(),
+	    None => idrive.to_string()
+	}
+    }
+
+    /// Loads the map at path as a new drive, returning its number
+    pub fn load<P:AsRef<Path>>(&mut self,path:P)->Result<usize> {
+	self.systems.push(FileSystemEntry::load(path)?);
+	Ok(self.systems.len() - 1)
+    }
+
+    /// Removes a drive; the following drives are renumbered
+    pub fn unload(&mut self,idrive:usize)->FileSystemEntry {
+	self.systems.remove(idrive)
+    }
+
+    /// Loads a drive again from its origin, keeping its label
+    pub fn reload(&mut self,idrive:usize)->Result<()> {
+	let fse = &mut self.systems[idrive];
+	fse.fs = FileSystem::from_file(&fse.origin)?;
+	Ok(())
+    }
+
+    pub fn set_label(&mut self,idrive:usize,label:Option<String>)->Result<()> {
+	if let Some(label) = &label {
+	    if !FileSystemEntry::is_valid_label(label) {
+		bail!("Invalid label {:?}",label);
+	    }
+	    if let Ok(other) = self.find(label) {
+		if other != idrive {
+		    bail!("Drive {} is already labelled {:?}",other,label);
+		}
+	    }
+	}
+	self.systems[idrive].label = label;
+	Ok(())
+    }
 }
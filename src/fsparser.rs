@@ -32,10 +32,28 @@ impl Expr<FsAtom> {
 	    [Token::True,rest @ ..] => Ok((Expr::True,rest)),
 	    [Token::Drive,Token::Unsigned(x),rest @ ..] =>
 		Ok((Expr::Atom(FsAtom::Drive(*x)),rest)),
+	    [Token::Drive,Token::Str(u),rest @ ..] =>
+		Ok((Expr::Atom(FsAtom::DriveLabel(u.clone())),rest)),
 	    [Token::Smaller,Token::Unsigned(x),rest @ ..] =>
 		Ok((Expr::Atom(FsAtom::Smaller(*x)),rest)),
 	    [Token::Larger,Token::Unsigned(x),rest @ ..] =>
 		Ok((Expr::Atom(FsAtom::Larger(*x)),rest)),
+	    [Token::Size,Token::Unsigned(x),rest @ ..] =>
+		Ok((Expr::Atom(FsAtom::Size(*x,*x)),rest)),
+	    [Token::Size,Token::Range(x,y),rest @ ..] =>
+		Ok((Expr::Atom(FsAtom::Size(*x,*y)),rest)),
+	    [Token::Depth,Token::Unsigned(x),rest @ ..] =>
+		Ok((Expr::Atom(FsAtom::Depth(*x as usize)),rest)),
+	    [Token::Empty,rest @ ..] => Ok((Expr::Atom(FsAtom::Empty),rest)),
+	    [Token::Dangling,rest @ ..] => Ok((Expr::Atom(FsAtom::Dangling),rest)),
+	    [Token::Contains,rest @ ..] => {
+		let (x,rest) = Self::eat_one(rest)?;
+		Ok((Expr::Atom(FsAtom::Contains(Box::new(x))),rest))
+	    },
+	    [Token::Under,rest @ ..] => {
+		let (x,rest) = Self::eat_one(rest)?;
+		Ok((Expr::Atom(FsAtom::Under(Box::new(x))),rest))
+	    },
 	    [Token::Before,Token::Date(d),rest @ ..] =>
 		Ok((Expr::Atom(FsAtom::Before(d.timestamp()?)),rest)),
 	    [Token::After,Token::Date(d),rest @ ..] =>
@@ -44,6 +62,12 @@ impl Expr<FsAtom> {
 		let rex = Regex::new(u)?;
 		Ok((Expr::Atom(FsAtom::NameMatch(rex)),rest))
 	    },
+	    [Token::Target,Token::Str(u),rest @ ..] => {
+		let rex = Regex::new(u)?;
+		Ok((Expr::Atom(FsAtom::TargetMatch(rex)),rest))
+	    },
+	    [Token::Var(name),rest @ ..] =>
+		Ok((Expr::Atom(FsAtom::Var(name.clone())),rest)),
 	    [Token::Str(u),rest @ ..] => {
 		let rex = Regex::new(u)?;
 		Ok((Expr::Atom(FsAtom::PathMatch(rex)),rest))
@@ -121,30 +145,85 @@ fn test_tokenize() {
 }
 
 #[test]
-fn test_parse() {
-    for u in &[
-	"a",
-	"a | b",
-	"a | b | c",
-	"a",
-	"a & b",
-	"a & b & c",
-	"a | b & c",
-	"a & b | c",
-	"a | b | c | d",
-	"a | b | c & d",
-	"a | b & c | d",
-	"a | b & c & d",
-	"a & b | c | d",
-	"a & b | c & d",
-	"a & b & c | d",
-	"a & b & c & d",
-	"a | (b | c)",
-	"a | (b | c)",
-	"a \\ b",
-	"a & b \\ c",
-	"a \\ b & c",
+fn test_sizes() {
+    for (u,x) in &[
+	("1234",Some(1234)),
+	("1k",Some(1024)),
+	("1K",Some(1024)),
+	("1KiB",Some(1024)),
+	("1kB",Some(1000)),
+	("2M",Some(2 << 20)),
+	("2MB",Some(2_000_000)),
+	("1.5G",Some(3 << 29)),
+	("1.5GB",Some(1_500_000_000)),
+	("3T",Some(3 << 40)),
+	("1PiB",Some(1 << 50)),
+	("0.001kB",Some(1)),
+	("16383P",Some(16383 << 50)),
+	("16384P",None),
+	("18446744073709551616",None),
+	("1.5",None),
+    ] {
+	let res = Token::tokenize(u);
+	println!("{:?} -> {:?}",u,res);
+	match (res,x) {
+	    (Ok(toks),Some(x)) =>
+		assert!(matches!(toks[..],[Token::Unsigned(y)] if y == *x)),
+	    (Err(_),None) => (),
+	    _ => panic!("Unexpected result for {:?}",u)
+	}
+    }
+    for (u,x,y) in &[
+	("10M..2G",10 << 20,2 << 30),
+	("1k..",1024,u64::MAX),
+	("0..0",0,0)
     ] {
+	let toks = Token::tokenize(u).unwrap();
+	assert!(matches!(toks[..],[Token::Range(a,b)] if a == *x && b == *y));
+    }
+    assert!(Token::tokenize("2G..1G").is_err());
+}
+
+/// Expressions of the tests of the parser, also evaluated on a tree
+/// by those of queries
+#[cfg(test)]
+pub const TEST_EXPRESSIONS : &[&str] = &[
+    "a",
+    "a | b",
+    "a | b | c",
+    "a",
+    "a & b",
+    "a & b & c",
+    "a | b & c",
+    "a & b | c",
+    "a | b | c | d",
+    "a | b | c & d",
+    "a | b & c | d",
+    "a | b & c & d",
+    "a & b | c | d",
+    "a & b | c & d",
+    "a & b & c | d",
+    "a & b & c & d",
+    "a | (b | c)",
+    "a | (b | c)",
+    "a \\ b",
+    "a & b \\ c",
+    "a \\ b & c",
+    "%depth 2",
+    "%empty & %depth 3",
+    "%contains %name '^\\.git$'",
+    "%under (%name ^src$ | %name ^lib$) & rs$",
+    "%contains (%name a & %empty) \\ %under %name b",
+    "%target ^/mnt/old-nas/ | %dangling",
+    "%size 10M..2G & %size 1.5GB..",
+    "%larger 1T \\ %size 2TiB",
+    "$media \\ %under ($junk | $tmp_2)",
+    "%drive photos-2019 | %drive 3",
+];
+
+#[test]
+fn test_parse() {
+    for u in TEST_EXPRESSIONS {
 	let toks = Token::tokenize(u).unwrap();
 	let expr = Expr::parse_from_tokens(&toks[..]).unwrap();
 	println!("{:?} -> {:?} -> {:?}",u,toks,expr);
@@ -1,4 +1,4 @@
-use anyhow::{bail,Result};
+use anyhow::{anyhow,bail,Result};
 
 use crate::{
     fsexpr::{FsDate}
@@ -9,14 +9,23 @@ pub enum Token {
     False,
     True,
     Unsigned(u64),
+    Range(u64,u64),
     Date(FsDate),
     Str(String),
+    Var(String),
     Drive,
     Name,
     Before,
     After,
     Smaller,
     Larger,
+    Size,
+    Depth,
+    Empty,
+    Contains,
+    Under,
+    Target,
+    Dangling,
     And,
     Or,
     Diff,
@@ -25,24 +34,34 @@ pub enum Token {
     Eof
 }
 
+pub const KEYWORDS : &[(&str,Token)] = &[
+    ("after",Token::After),
+    ("before",Token::Before),
+    ("contains",Token::Contains),
+    ("dangling",Token::Dangling),
+    ("depth",Token::Depth),
+    ("drive",Token::Drive),
+    ("empty",Token::Empty),
+    ("f",Token::False),
+    ("larger",Token::Larger),
+    ("name",Token::Name),
+    ("size",Token::Size),
+    ("smaller",Token::Smaller),
+    ("t",Token::True),
+    ("target",Token::Target),
+    ("under",Token::Under)
+];
+
 impl Token {
     fn eat(u:&[char])->Result<(Self,&[char])> {
 	match u {
 	    [w,rest @ ..] if w.is_whitespace() => Self::eat(rest),
 	    ['%',rest @ ..] => {
 		let (kw,rest) = Self::eat_keyword(rest,String::new())?;
-		let kw =
-		    match kw.as_str() {
-			"after" => Self::After,
-			"before" => Self::Before,
-			"drive" => Self::Drive,
-			"f" => Self::False,
-			"larger" => Self::Larger,
-			"name" => Self::Name,
-			"smaller" => Self::Smaller,
-			"t" => Self::True,
-			_ => bail!("Unknown keyword {:?}",kw)
-		    };
+		let Some((_,kw)) = KEYWORDS.iter().find(|(k,_)| *k == kw) else {
+		    bail!("Unknown keyword {:?}",kw)
+		};
+		let kw = kw.clone();
 		Ok((kw,rest))
 	    },
 	    ['&',rest @ ..] => Ok((Self::And,rest)),
@@ -51,6 +70,13 @@ impl Token {
 	    ['(',rest @ ..] => Ok((Self::LPar,rest)),
 	    [')',rest @ ..] => Ok((Self::RPar,rest)),
 	    ['\'',rest @ ..] => Self::eat_quoted_str(rest,String::new()),
+	    ['$',rest @ ..] if rest.first()
+		.is_some_and(|c| c.is_ascii_alphabetic() || *c == '_') => {
+		let n = rest.iter()
+		    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+		    .count();
+		Ok((Self::Var(rest[0..n].iter().collect()),&rest[n..]))
+	    },
 	    ['0'..='9',
 	     '0'..='9',
 	     '0'..='9',
@@ -68,7 +94,17 @@ impl Token {
 	    },
 	    ['0'..='9',..] => {
 		let (n,rest) = Self::parse_size(u)?;
-		Ok((Self::Unsigned(n),rest))
+		match rest {
+		    ['.','.',rest @ ..] if Self::starts_with_digit(rest) => {
+			let (m,rest) = Self::parse_size(rest)?;
+			if m < n {
+			    bail!("Empty size range");
+			}
+			Ok((Self::Range(n,m),rest))
+		    },
+		    ['.','.',rest @ ..] => Ok((Self::Range(n,u64::MAX),rest)),
+		    _ => Ok((Self::Unsigned(n),rest))
+		}
 	    },
 	    [c @ ('a'..='z'|'A'..='Z'
 		  |'/'|'.'|','|'*'|'?'|'$'|'^'|'-'|'_'),
@@ -78,31 +114,69 @@ impl Token {
 	}
     }
 
+    // Sizes are a decimal number followed by an optional unit.  The
+    // suffixes k (or K), M, G, T and P and their KiB, MiB... forms
+    // are powers of 1024, while kB (or KB), MB, GB... are powers
+    // of 1000.
     fn parse_size(u:&[char])->Result<(u64,&[char])> {
-	let (n,rest) = Self::parse_u64(u,String::new())?;
-	match rest {
-	    ['G',rest @ ..] => Ok((n << 30,rest)),
-	    ['M',rest @ ..] => Ok((n << 20,rest)),
-	    ['k',rest @ ..] => Ok((n << 10,rest)),
-	    _ => Ok((n,rest))
+	let (int,rest) = Self::eat_digits(u);
+	let (frac,rest) =
+	    match rest {
+		['.',rest @ ..] if Self::starts_with_digit(rest) =>
+		    Self::eat_digits(rest),
+		_ => (&rest[0..0],rest)
+	    };
+	let (mult,rest) = Self::parse_unit(rest);
+	if !frac.is_empty() && mult == 1 {
+	    bail!("Fractional number of bytes");
+	}
+	let too_large = || anyhow!("Size too large");
+	let mut num : u128 = 0;
+	let mut den : u128 = 1;
+	for &c in int.iter().chain(frac.iter()) {
+	    num = num.checked_mul(10)
+		.and_then(|n| n.checked_add(c as u128 - '0' as u128))
+		.ok_or_else(too_large)?;
 	}
+	for _ in frac {
+	    den = den.checked_mul(10).ok_or_else(too_large)?;
+	}
+	let x = num.checked_mul(mult).ok_or_else(too_large)? / den;
+	let x = u64::try_from(x).map_err(|_| too_large())?;
+	Ok((x,rest))
     }
 
-    fn parse_u64(mut u:&[char],mut buf:String)->Result<(u64,&[char])> {
-	loop {
+    fn parse_unit(u:&[char])->(u128,&[char]) {
+	let (base,rest) =
 	    match u {
-		[c @ '0'..='9',rest @ ..] => {
-		    buf.push(*c);
-		    u = rest;
-		},
-		_ => {
-		    let x : u64 = buf.parse()?;
-		    return Ok((x,u))
-		}
-	    }
+		[c @ ('k'|'K'|'M'|'G'|'T'|'P'),rest @ ..] => (*c,rest),
+		['B',rest @ ..] => return (1,rest),
+		_ => return (1,u)
+	    };
+	let power =
+	    match base {
+		'k' | 'K' => 1,
+		'M' => 2,
+		'G' => 3,
+		'T' => 4,
+		_ => 5
+	    };
+	match rest {
+	    ['i','B',rest @ ..] => (1024u128.pow(power),rest),
+	    ['B',rest @ ..] => (1000u128.pow(power),rest),
+	    _ => (1024u128.pow(power),rest)
 	}
     }
 
+    fn starts_with_digit(u:&[char])->bool {
+	u.first().is_some_and(|c| c.is_ascii_digit())
+    }
+
+    fn eat_digits(u:&[char])->(&[char],&[char]) {
+	let n = u.iter().take_while(|c| c.is_ascii_digit()).count();
+	u.split_at(n)
+    }
+
     fn parse_i32(u:&[char])->Result<i32> {
 	let v : String = u.iter().collect();
 	Ok(v.parse()?)
@@ -0,0 +1,495 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read,Seek,SeekFrom,Write};
+use std::path::{Path,PathBuf};
+use anyhow::{bail,Result};
+use log::warn;
+use tz::UtcDateTime;
+
+use crate::{
+    fsmap::*,
+    import::{Builder,Kind}
+};
+
+// Device number given to the file system of an image
+const IMAGE_DEV : u64 = 1;
+
+// Volume descriptors of ISO9660 start at this sector
+const ISO_FIRST_DESCRIPTOR : u64 = 16;
+const ISO_SECTOR : u64 = 2048;
+
+/// File system images that can be read without mounting them
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum ImageFormat {
+    Iso9660,
+    Fat
+}
+
+/// A directory record of ISO9660
+struct Record {
+    extent:u32,
+    size:u64,
+    time:i64,
+    flags:u8,
+    name:Vec<u8>,
+    system_use:Vec<u8>
+}
+
+/// Reader of ISO9660 images, using the Rock Ridge or Joliet extensions
+/// for names when present
+struct Iso<R> {
+    input:R,
+    // Length of the image, bounding the extents read
+    len:u64,
+    block_size:u64,
+    rock_ridge:bool,
+    // Bytes to skip at the start of system use areas, from the SP
+    // entry of the root
+    susp_skip:usize,
+    joliet:bool,
+    visited:HashSet<u32>
+}
+
+fn le32(u:&[u8])->u32 {
+    u32::from_le_bytes([u[0],u[1],u[2],u[3]])
+}
+
+// Seven byte dates of directory records and Rock Ridge TF entries
+fn iso_time(d:&[u8])->Option<i64> {
+    let t = UtcDateTime::new(1900 + d[0] as i32,d[1],d[2],d[3],d[4],d[5],0).ok()?;
+    // The offset from GMT is in units of 15 minutes
+    Some(t.unix_time() - d[6] as i8 as i64 * 900)
+}
+
+impl Record {
+    fn parse(u:&[u8])->Option<Self> {
+	let len = *u.first()? as usize;
+	if len < 34 || len > u.len() {
+	    return None;
+	}
+	let name_len = u[32] as usize;
+	let name_end = 33 + name_len;
+	// The identifier is padded to an even length
+	let su_start = name_end + (1 - name_len % 2);
+	if su_start > len {
+	    return None;
+	}
+	Some(Self {
+	    extent:le32(&u[2..]),
+	    size:le32(&u[10..]) as u64,
+	    time:iso_time(&u[18..25]).unwrap_or(0),
+	    flags:u[25],
+	    name:u[33..name_end].to_vec(),
+	    system_use:u[su_start..len].to_vec()
+	})
+    }
+
+    fn is_dir(&self)->bool {
+	self.flags & 2 != 0
+    }
+
+    // The records for the directory itself and its parent
+    fn is_self_or_parent(&self)->bool {
+	matches!(&self.name[..],[0] | [1])
+    }
+}
+
+impl<R:Read+Seek> Iso<R> {
+    fn read_at(&mut self,offset:u64,len:usize)->Result<Vec<u8>> {
+	if offset.checked_add(len as u64).map_or(true,|end| end > self.len) {
+	    bail!("Extent of {} bytes at offset {} is beyond the end of the image",len,offset);
+	}
+	let mut buf = vec![0;len];
+	self.input.seek(SeekFrom::Start(offset))?;
+	self.input.read_exact(&mut buf)?;
+	Ok(buf)
+    }
+
+    fn new(mut input:R)->Result<(Self,Record)> {
+	let mut primary = None;
+	let mut joliet = None;
+	let mut block_size = ISO_SECTOR;
+	for sector in ISO_FIRST_DESCRIPTOR.. {
+	    let mut vd = vec![0;ISO_SECTOR as usize];
+	    input.seek(SeekFrom::Start(sector * ISO_SECTOR))?;
+	    input.read_exact(&mut vd)?;
+	    if &vd[1..6] != b"CD001" {
+		bail!("Bad volume descriptor at sector {}",sector);
+	    }
+	    match vd[0] {
+		1 => {
+		    block_size = u16::from_le_bytes([vd[128],vd[129]]) as u64;
+		    primary = Record::parse(&vd[156..190]);
+		},
+		2 if vd[88] == 0x25 && vd[89] == 0x2f && [0x40,0x43,0x45].contains(&vd[90]) =>
+		    joliet = Record::parse(&vd[156..190]),
+		255 => break,
+		_ => ()
+	    }
+	}
+	let Some(primary) = primary else { bail!("No primary volume descriptor") };
+	if ![512,1024,2048].contains(&block_size) {
+	    bail!("Unsupported logical block size {}",block_size);
+	}
+	let len = input.seek(SeekFrom::End(0))?;
+	let mut iso = Self {
+	    input,
+	    len,
+	    block_size,
+	    rock_ridge:false,
+	    susp_skip:0,
+	    joliet:false,
+	    visited:HashSet::new()
+	};
+	// Rock Ridge is announced by an SP entry in the first record of
+	// the root directory
+	let first = iso.read_at(primary.extent as u64 * block_size,block_size as usize)?;
+	if let Some(dot) = Record::parse(&first) {
+	    let su = &dot.system_use;
+	    if su.len() >= 7 && &su[0..2] == b"SP" && su[4] == 0xbe && su[5] == 0xef {
+		iso.rock_ridge = true;
+		iso.susp_skip = su[6] as usize;
+	    }
+	}
+	if iso.rock_ridge {
+	    return Ok((iso,primary));
+	}
+	match joliet {
+	    Some(root) => {
+		iso.joliet = true;
+		Ok((iso,root))
+	    },
+	    None => Ok((iso,primary))
+	}
+    }
+
+    // All the records of a directory, with the sizes of files spanning
+    // several extents added up
+    fn read_dir(&mut self,extent:u32,size:u64)->Result<Vec<Record>> {
+	let data = self.read_at(extent as u64 * self.block_size,size as usize)?;
+	let bs = self.block_size as usize;
+	let mut records = Vec::new();
+	let mut pending = 0;
+	let mut pos = 0;
+	while pos < data.len() {
+	    // Records do not cross block boundaries
+	    if data[pos] == 0 {
+		pos = (pos / bs + 1) * bs;
+		continue;
+	    }
+	    let Some(mut rec) = Record::parse(&data[pos..]) else {
+		bail!("Bad directory record in extent {}",extent)
+	    };
+	    pos += data[pos] as usize;
+	    if rec.flags & 0x80 != 0 {
+		pending += rec.size;
+		continue;
+	    }
+	    rec.size += pending;
+	    pending = 0;
+	    records.push(rec);
+	}
+	Ok(records)
+    }
+
+    // The entries of the system use area of a record, following
+    // continuation areas
+    fn system_use(&mut self,rec:&Record)->Result<Vec<([u8;2],Vec<u8>)>> {
+	let mut entries = Vec::new();
+	let mut area = rec.system_use.get(self.susp_skip..).unwrap_or(&[]).to_vec();
+	for _ in 0..16 {
+	    let mut next = None;
+	    let mut pos = 0;
+	    while pos + 4 <= area.len() {
+		let sig = [area[pos],area[pos + 1]];
+		let len = area[pos + 2] as usize;
+		if len < 4 || pos + len > area.len() {
+		    break;
+		}
+		let data = &area[pos + 4..pos + len];
+		match &sig {
+		    b"ST" => break,
+		    b"CE" if data.len() >= 24 =>
+			next = Some((le32(&data[0..]) as u64,le32(&data[8..]) as u64,le32(&data[16..]) as usize)),
+		    _ => entries.push((sig,data.to_vec()))
+		}
+		pos += len;
+	    }
+	    let Some((block,offset,len)) = next else { break };
+	    area = self.read_at(block * self.block_size + offset,len)?;
+	}
+	Ok(entries)
+    }
+
+    fn name(&self,rec:&Record)->Vec<u8> {
+	let name =
+	    if self.joliet {
+		let units : Vec<u16> = rec.name.chunks_exact(2)
+		    .map(|c| u16::from_be_bytes([c[0],c[1]]))
+		    .collect();
+		String::from_utf16_lossy(&units).into_bytes()
+	    } else {
+		// Names are shown in lower case, as mount does by default
+		rec.name.to_ascii_lowercase()
+	    };
+	let mut name = match name.iter().rposition(|&c| c == b';') {
+	    Some(i) => name[..i].to_vec(),
+	    None => name
+	};
+	if !rec.is_dir() && name.ends_with(b".") {
+	    name.pop();
+	}
+	name
+    }
+
+    fn walk(&mut self,builder:&mut Builder,path:&Path,extent:u32,size:u64)->Result<()> {
+	if !self.visited.insert(extent) {
+	    warn!("Directory {:?} was already visited",path);
+	    return Ok(());
+	}
+	for rec in self.read_dir(extent,size)? {
+	    if rec.is_self_or_parent() {
+		continue;
+	    }
+	    let mut name = self.name(&rec);
+	    let mut kind = if rec.is_dir() { Some(Kind::Dir) } else { None };
+	    let mut time = rec.time;
+	    let mut dir = (rec.extent,rec.size);
+	    if self.rock_ridge {
+		let mut nm = Vec::new();
+		let mut target = Vec::new();
+		let mut symlink = false;
+		let mut sep = false;
+		for (sig,data) in self.system_use(&rec)? {
+		    match (&sig,&data[..]) {
+			// Relocated directories are listed where their
+			// CL entry is
+			(b"RE",_) => {
+			    kind = None;
+			    name.clear();
+			    break;
+			},
+			(b"NM",[flags,rest @ ..]) if flags & 6 == 0 => nm.extend_from_slice(rest),
+			(b"PX",[m0,m1,m2,m3,..]) => {
+			    let mode = u32::from_le_bytes([*m0,*m1,*m2,*m3]);
+			    kind = match mode & 0o170000 {
+				0o040000 => Some(Kind::Dir),
+				0o100000 | 0o120000 => None,
+				_ => Some(Kind::Other(0))
+			    };
+			},
+			(b"SL",[_,components @ ..]) => {
+			    symlink = true;
+			    let mut rest = components;
+			    while let &[flags,len,ref tail @ ..] = rest {
+				let len = (len as usize).min(tail.len());
+				if sep {
+				    target.push(b'/');
+				}
+				sep = true;
+				match flags & 0xe {
+				    2 => target.push(b'.'),
+				    4 => target.extend_from_slice(b".."),
+				    8 => {
+					target.push(b'/');
+					sep = false;
+				    },
+				    _ => target.extend_from_slice(&tail[..len])
+				}
+				if flags & 1 != 0 {
+				    sep = false;
+				}
+				rest = &tail[len..];
+			    }
+			},
+			(b"CL",[..]) if data.len() >= 4 => {
+			    let location = le32(&data);
+			    let first = self.read_at(location as u64 * self.block_size,self.block_size as usize)?;
+			    if let Some(dot) = Record::parse(&first) {
+				dir = (location,dot.size);
+				kind = Some(Kind::Dir);
+			    }
+			},
+			(b"TF",[flags,rest @ ..]) if flags & 0x82 == 2 => {
+			    let i = (flags & 1) as usize * 7;
+			    if let Some(t) = rest.get(i..i + 7).and_then(iso_time) {
+				time = t;
+			    }
+			},
+			_ => ()
+		    }
+		}
+		if name.is_empty() {
+		    continue;
+		}
+		if !nm.is_empty() {
+		    name = nm;
+		}
+		if symlink {
+		    kind = Some(Kind::Symlink(String::from_utf8_lossy(&target).into_owned().into()));
+		}
+	    }
+	    let path = path.join(String::from_utf8_lossy(&name).as_ref());
+	    match kind {
+		Some(Kind::Dir) => {
+		    builder.insert(&path,Kind::Dir)?;
+		    self.walk(builder,&path,dir.0,dir.1)?;
+		},
+		Some(Kind::Other(_)) => {
+		    let ino = builder.inode(rec.size,time);
+		    builder.insert(&path,Kind::Other(ino))?;
+		},
+		Some(kind) => builder.insert(&path,kind)?,
+		None => {
+		    let ino = builder.inode(rec.size,time);
+		    builder.insert(&path,Kind::File(ino))?;
+		}
+	    }
+	}
+	Ok(())
+    }
+}
+
+// Times of FAT are in local time, which is unknown, and are taken as
+// UTC, as are the dates of listings without a time zone in import.rs
+fn fat_time(t:fatfs::DateTime)->i64 {
+    UtcDateTime::new(t.date.year as i32,t.date.month as u8,t.date.day as u8,
+		     t.time.hour as u8,t.time.min as u8,t.time.sec as u8,0)
+	.map(|t| t.unix_time())
+	.unwrap_or(0)
+}
+
+fn walk_fat<T:fatfs::ReadWriteSeek>(dir:fatfs::Dir<T>,path:&Path,builder:&mut Builder)->Result<()> {
+    for entry in dir.iter() {
+	let entry = entry?;
+	let name = entry.file_name();
+	if name == "." || name == ".." {
+	    continue;
+	}
+	let path = path.join(&name);
+	if entry.is_dir() {
+	    builder.insert(&path,Kind::Dir)?;
+	    walk_fat(entry.to_dir(),&path,builder)?;
+	} else {
+	    let ino = builder.inode(entry.len(),fat_time(entry.modified()));
+	    builder.insert(&path,Kind::File(ino))?;
+	}
+    }
+    Ok(())
+}
+
+impl ImageFormat {
+    /// Recognizes the format from the contents of the image
+    pub fn detect<R:Read+Seek>(input:&mut R)->Result<Self> {
+	let mut buf = [0;6];
+	input.seek(SeekFrom::Start(ISO_FIRST_DESCRIPTOR * ISO_SECTOR))?;
+	if input.read_exact(&mut buf).is_ok() && &buf[1..6] == b"CD001" {
+	    return Ok(Self::Iso9660);
+	}
+	let mut boot = [0;512];
+	input.seek(SeekFrom::Start(0))?;
+	if input.read_exact(&mut boot).is_ok() && boot[510..512] == [0x55,0xaa] {
+	    return Ok(Self::Fat);
+	}
+	bail!("Unsupported image, expected ISO9660 or FAT")
+    }
+}
+
+/// Builds a map of the file system in an image
+pub fn read<R:Read+Write+Seek>(mut input:R)->Result<FileSystem> {
+    let mut builder = Builder::new(IMAGE_DEV);
+    match ImageFormat::detect(&mut input)? {
+	ImageFormat::Iso9660 => {
+	    let (mut iso,root) = Iso::new(input)?;
+	    iso.walk(&mut builder,&PathBuf::new(),root.extent,root.size)?;
+	},
+	ImageFormat::Fat => {
+	    input.seek(SeekFrom::Start(0))?;
+	    let fs = fatfs::FileSystem::new(input,fatfs::FsOptions::new())?;
+	    walk_fat(fs.root_dir(),&PathBuf::new(),&mut builder)?;
+	}
+    }
+    Ok(builder.finish(None))
+}
+
+pub fn read_image(path:&Path)->Result<FileSystem> {
+    read(File::open(path)?)
+}
+
+#[cfg(test)]
+fn file_size(fs:&FileSystem,dir:&Directory,name:&str)->Option<u64> {
+    match dir.entries.iter().find(|(n,_)| n == name)? {
+	(_,Entry::File(ino)) => Some(fs.mounts.get_device(IMAGE_DEV)?.get_inode(*ino)?.size),
+	_ => None
+    }
+}
+
+#[test]
+fn test_iso() {
+    let record = |extent:u32,size:u32,flags:u8,name:&[u8],su:&[u8]| {
+	let len = 33 + name.len() + (1 - name.len() % 2) + su.len();
+	let mut r = vec![0;len];
+	r[0] = len as u8;
+	r[2..6].copy_from_slice(&extent.to_le_bytes());
+	r[10..14].copy_from_slice(&size.to_le_bytes());
+	r[18..25].copy_from_slice(&[119,3,8,0,26,40,4]);
+	r[25] = flags;
+	r[32] = name.len() as u8;
+	r[33..33 + name.len()].copy_from_slice(name);
+	r[len - su.len()..].copy_from_slice(su);
+	r
+    };
+    let sector = |n:usize| n * ISO_SECTOR as usize;
+    let mut image = vec![0;sector(21)];
+    let put = |image:&mut Vec<u8>,at:usize,records:&[Vec<u8>]| {
+	let data = records.concat();
+	image[at..at + data.len()].copy_from_slice(&data);
+    };
+    image[sector(16)] = 1;
+    image[sector(16) + 1..sector(16) + 6].copy_from_slice(b"CD001");
+    image[sector(16) + 128..sector(16) + 130].copy_from_slice(&2048u16.to_le_bytes());
+    put(&mut image,sector(16) + 156,&[record(18,2048,2,&[0],&[])]);
+    image[sector(17)] = 255;
+    image[sector(17) + 1..sector(17) + 6].copy_from_slice(b"CD001");
+    put(&mut image,sector(18),&[record(18,2048,2,&[0],b"SP\x07\x01\xbe\xef\x00"),
+				 record(18,2048,2,&[1],&[]),
+				 record(19,2048,2,b"DOCS",&[]),
+				 record(20,5,0,b"README.;1",b"NM\x0e\x01\x00ReadMe.md"),
+				 record(0,0,0,b"LINK.;1",b"SL\x11\x01\x00\x08\x00\x00\x03etc\x00\x03rc0")]);
+    put(&mut image,sector(19),&[record(19,2048,2,&[0],&[]),
+				 record(18,2048,2,&[1],&[]),
+				 record(20,3000,0x80,b"BIG.DAT;1",&[]),
+				 record(20,4000,0,b"BIG.DAT;1",&[])]);
+    let fs = read(std::io::Cursor::new(image.clone())).unwrap();
+    let names : Vec<&std::ffi::OsString> = fs.root.entries.iter().map(|(n,_)| n).collect();
+    assert_eq!(names,["ReadMe.md","docs","link"]);
+    let Entry::Dir(docs) = &fs.root.entries[1].1 else { panic!() };
+    assert_eq!(file_size(&fs,docs,"big.dat"),Some(7000));
+    let &Entry::File(ino) = &fs.root.entries[0].1 else { panic!() };
+    let fi = fs.mounts.get_device(IMAGE_DEV).unwrap().get_inode(ino).unwrap();
+    assert_eq!((fi.size,fi.unix_time()),(5,1_552_001_160));
+    let Entry::Symlink(target) = &fs.root.entries[2].1 else { panic!() };
+    assert_eq!(target,"/etc/rc0");
+
+    let mut bad = image.clone();
+    bad[sector(16) + 128..sector(16) + 130].fill(0);
+    assert!(read(std::io::Cursor::new(bad)).is_err());
+    let mut bad = image;
+    bad[sector(16) + 166..sector(16) + 170].fill(0xff);
+    assert!(read(std::io::Cursor::new(bad)).is_err());
+}
+
+#[test]
+fn test_fat() {
+    let mut disk = std::io::Cursor::new(vec![0;1 << 20]);
+    fatfs::format_volume(&mut disk,fatfs::FormatVolumeOptions::new()).unwrap();
+    {
+	let fs = fatfs::FileSystem::new(&mut disk,fatfs::FsOptions::new()).unwrap();
+	let dir = fs.root_dir().create_dir("Photos").unwrap();
+	dir.create_file("Holiday picture.jpg").unwrap().write_all(b"jpeg").unwrap();
+    }
+    disk.set_position(0);
+    let fs = read(disk).unwrap();
+    let Some((_,Entry::Dir(photos))) = fs.root.entries.iter().find(|(n,_)| n == "Photos") else { panic!() };
+    assert_eq!(file_size(&fs,photos,"Holiday picture.jpg"),Some(4));
+}
@@ -0,0 +1,469 @@
+use std::collections::{BTreeMap,HashMap};
+use std::ffi::OsString;
+use std::io::BufRead;
+use std::path::{Component,Path,PathBuf};
+use std::time::{SystemTime,UNIX_EPOCH};
+use anyhow::{anyhow,bail,Result};
+use log::warn;
+use tz::UtcDateTime;
+
+use crate::{
+    checksum::Digest,
+    fsmap::*,
+    ncdu
+};
+
+// Device number given to imported listings
+const IMPORT_DEV : u64 = 1;
+
+const MONTHS : &[&str] = &["Jan","Feb","Mar","Apr","May","Jun",
+			   "Jul","Aug","Sep","Oct","Nov","Dec"];
+
+/// Text listings that can be turned into maps
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Format {
+    // find DIR -printf '%y %s %T@ %p -> %l\n'
+    FindPrintf,
+    // ls -lR DIR
+    LsR,
+    // tar tvf ARCHIVE
+    TarTv,
+    // ncdu -o FILE
+    Ncdu
+}
+
+pub enum Kind {
+    Dir,
+    File(u64),
+    Symlink(OsString),
+    Other(u64)
+}
+
+struct Node {
+    kind:Kind,
+    children:BTreeMap<OsString,Node>
+}
+
+/// Tree of entries being imported, all on one device
+pub struct Builder {
+    dev:u64,
+    root:Node,
+    device:Device,
+    next_ino:u64,
+    // Inodes of files, for hard links
+    files:HashMap<PathBuf,u64>
+}
+
+/// An entry of a long listing, as given by ls -l or tar tv
+#[derive(Debug,PartialEq,Eq)]
+struct LongEntry<'a> {
+    kind:char,
+    size:u64,
+    time:i64,
+    name:&'a str
+}
+
+impl Format {
+    pub fn parse(u:&str)->Result<Self> {
+	match u {
+	    "find-printf" => Ok(Self::FindPrintf),
+	    "lsR" => Ok(Self::LsR),
+	    "tar-tv" => Ok(Self::TarTv),
+	    "ncdu" => Ok(Self::Ncdu),
+	    _ => bail!("Unknown format {:?}, expected find-printf, lsR, tar-tv or ncdu",u)
+	}
+    }
+}
+
+impl Node {
+    fn new(kind:Kind)->Self {
+	Self { kind,children:BTreeMap::new() }
+    }
+
+    fn into_entry(self,dev:u64)->Entry {
+	match self.kind {
+	    Kind::Dir => {
+		let mut dir = Directory::new(dev);
+		for (name,node) in self.children {
+		    dir.insert(name,node.into_entry(dev));
+		}
+		Entry::Dir(dir)
+	    },
+	    Kind::File(ino) => Entry::File(ino),
+	    Kind::Symlink(target) => Entry::Symlink(target),
+	    Kind::Other(ino) => Entry::Other(ino)
+	}
+    }
+}
+
+// Byte offsets and text of the whitespace-separated tokens of u
+fn tokens(u:&str)->Vec<(usize,&str)> {
+    let mut toks = Vec::new();
+    let mut start = None;
+    for (i,c) in u.char_indices().chain(std::iter::once((u.len(),' '))) {
+	match (c.is_whitespace(),start) {
+	    (true,Some(j)) => {
+		toks.push((j,&u[j..i]));
+		start = None;
+	    },
+	    (false,None) => start = Some(i),
+	    _ => ()
+	}
+    }
+    toks
+}
+
+fn is_digits(u:&str,n:usize)->bool {
+    u.len() == n && u.bytes().all(|c| c.is_ascii_digit())
+}
+
+// Parses HH:MM or HH:MM:SS.NNN
+fn parse_clock(u:&str)->Option<(u8,u8)> {
+    let (h,rest) = u.split_once(':')?;
+    let m = rest.get(..2)?;
+    if !is_digits(h,2) || !is_digits(m,2) {
+	return None;
+    }
+    Some((h.parse().ok()?,m.parse().ok()?))
+}
+
+fn unix_time(year:i32,month:u8,day:u8,(hour,minute):(u8,u8))->Option<i64> {
+    Some(UtcDateTime::new(year,month,day,hour,minute,0,0).ok()?.unix_time())
+}
+
+// Parses a date of a long listing starting with toks[0], returning
+// its time and the number of tokens it spans.  Dates of ls without
+// a year are within the last year.  Unless --full-time gave the time
+// zone of the listing, it is unknown and dates are taken as UTC, as
+// in image.rs for FAT; the missing year is then that of now in UTC.
+fn parse_date(toks:&[(usize,&str)],now:i64)->Option<(i64,usize)> {
+    let word = |i:usize| toks.get(i).map(|&(_,u)| u);
+    let first = word(0)?;
+    if let [y,m,d] = first.split('-').collect::<Vec<&str>>()[..] {
+	if !is_digits(y,4) || !is_digits(m,2) || !is_digits(d,2) {
+	    return None;
+	}
+	let time = unix_time(y.parse().ok()?,m.parse().ok()?,d.parse().ok()?,
+			     parse_clock(word(1)?)?)?;
+	// Time zone of --full-time, as +HHMM or -HHMM
+	let zone = word(2).filter(|z| z.len() == 5 && z.starts_with(['+','-'])
+				  && is_digits(&z[1..],4));
+	let Some(zone) = zone else { return Some((time,2)) };
+	let offset = zone[1..3].parse::<i64>().ok()? * 3600
+	    + zone[3..].parse::<i64>().ok()? * 60;
+	return Some((if zone.starts_with('-') { time + offset } else { time - offset },3));
+    }
+    let month = MONTHS.iter().position(|&m| m == first)? as u8 + 1;
+    let day = word(1)?.parse().ok()?;
+    let third = word(2)?;
+    let time =
+	if is_digits(third,4) {
+	    unix_time(third.parse().ok()?,month,day,(0,0))?
+	} else {
+	    let clock = parse_clock(third)?;
+	    let year = UtcDateTime::from_timespec(now,0).ok()?.year();
+	    let time = unix_time(year,month,day,clock)?;
+	    if time > now + 86400 {
+		unix_time(year - 1,month,day,clock)?
+	    } else {
+		time
+	    }
+	};
+    Some((time,3))
+}
+
+// Parses an entry of ls -l or tar tv.  The size is the token in front
+// of the date.
+fn parse_long(line:&str,now:i64)->Option<LongEntry<'_>> {
+    let toks = tokens(line);
+    let &(_,mode) = toks.first()?;
+    let kind = mode.chars().next()?;
+    if mode.len() < 10 || !"-dlbcpsDh".contains(kind) {
+	return None;
+    }
+    for i in 2..toks.len() {
+	if let Some((time,n)) = parse_date(&toks[i..],now) {
+	    let size = toks[i - 1].1.parse().ok()?;
+	    let &(j,last) = toks.get(i + n - 1)?;
+	    let name = line.get(j + last.len() + 1..)?;
+	    if name.is_empty() {
+		return None;
+	    }
+	    return Some(LongEntry { kind,size,time,name });
+	}
+    }
+    None
+}
+
+fn relative(path:&Path)->PathBuf {
+    path.components()
+	.filter(|c| matches!(c,Component::Normal(_)))
+	.collect()
+}
+
+impl Builder {
+    pub fn new(dev:u64)->Self {
+	Self {
+	    dev,
+	    root:Node::new(Kind::Dir),
+	    device:Device::new(),
+	    next_ino:1,
+	    files:HashMap::new()
+	}
+    }
+
+    pub fn inode(&mut self,size:u64,time:i64)->u64 {
+	let ino = self.next_ino;
+	self.next_ino += 1;
+	self.device.insert_inode(ino,FileInfo { size,time:(time / 60) as i32,digest:None,mtime:Some(time) });
+	ino
+    }
+
+    pub fn set_digest(&mut self,ino:u64,digest:Digest) {
+	if let Some(fi) = self.device.inodes.get_mut(&ino) {
+	    fi.digest = Some(digest);
+	}
+    }
+
+    // Adds an entry given by its type letter, as used by ls and tar
+    fn add(&mut self,path:&Path,kind:char,size:u64,time:i64,name:&str)->Result<()> {
+	let path = relative(path);
+	let kind =
+	    match kind {
+		'd' => Kind::Dir,
+		'-' | 'f' => {
+		    let ino = self.inode(size,time);
+		    self.files.insert(path.clone(),ino);
+		    Kind::File(ino)
+		},
+		'l' => {
+		    let Some((_,target)) = name.split_once(" -> ") else {
+			bail!("No target for symbolic link {:?}",path)
+		    };
+		    Kind::Symlink(target.into())
+		},
+		'h' => {
+		    let Some((_,target)) = name.split_once(" link to ") else {
+			bail!("No target for hard link {:?}",path)
+		    };
+		    match self.files.get(&relative(Path::new(target))) {
+			Some(&ino) => Kind::File(ino),
+			None => Kind::File(self.inode(size,time))
+		    }
+		},
+		_ => Kind::Other(self.inode(size,time))
+	    };
+	self.insert(&path,kind)
+    }
+
+    /// Adds an entry, creating the directories above it
+    pub fn insert(&mut self,path:&Path,kind:Kind)->Result<()> {
+	let path = relative(path);
+	let mut node = &mut self.root;
+	let mut names = path.iter().peekable();
+	while let Some(name) = names.next() {
+	    if !matches!(node.kind,Kind::Dir) {
+		bail!("{:?} is below a non-directory",path);
+	    }
+	    if names.peek().is_none() {
+		match node.children.get_mut(name) {
+		    // Directories are created when first seen in a path
+		    Some(old) if matches!((&old.kind,&kind),(Kind::Dir,Kind::Dir)) => (),
+		    Some(old) if matches!(old.kind,Kind::Dir) || matches!(kind,Kind::Dir) =>
+			bail!("{:?} is both a directory and a non-directory",path),
+		    Some(old) => old.kind = kind,
+		    None => { node.children.insert(name.to_os_string(),Node::new(kind)); }
+		}
+		return Ok(());
+	    }
+	    node = node.children.entry(name.to_os_string())
+		.or_insert_with(|| Node::new(Kind::Dir));
+	}
+	Ok(())
+    }
+
+    /// The root directory of the tree, and the inodes of its entries
+    pub fn into_parts(self)->(Directory,Device) {
+	let Entry::Dir(root) = self.root.into_entry(self.dev) else { unreachable!() };
+	(root,self.device)
+    }
+
+    /// The map of the tree, with base as the directory it was listed from
+    pub fn finish(self,base:Option<OsString>)->FileSystem {
+	let dev = self.dev;
+	let (root,device) = self.into_parts();
+	let mut mounts = Mounts::new();
+	mounts.devices.insert(dev,device);
+	FileSystem { mounts,root,base }
+    }
+}
+
+// Name of an entry of a long listing, without the target of links
+fn entry_name<'a>(e:&LongEntry<'a>)->&'a str {
+    match e.kind {
+	'l' => e.name.split_once(" -> ").map_or(e.name,|(n,_)| n),
+	'h' => e.name.split_once(" link to ").map_or(e.name,|(n,_)| n),
+	_ => e.name
+    }
+}
+
+fn import_find<R:BufRead>(input:R,builder:&mut Builder)->Result<Option<OsString>> {
+    let mut root : Option<String> = None;
+    for (iline,line) in input.lines().enumerate() {
+	let line = line?;
+	let toks = tokens(&line);
+	let parsed = (|| {
+	    let &[(_,kind),(_,size),(_,time),(j,_),..] = &toks[..] else { return None };
+	    let kind = kind.chars().next().filter(|_| kind.len() == 1)?;
+	    let size : u64 = size.parse().ok()?;
+	    let time : f64 = time.parse().ok()?;
+	    Some((kind,size,time as i64,&line[j..]))
+	})();
+	let Some((kind,size,time,name)) = parsed else {
+	    warn!("Line {}: cannot parse {:?}",iline + 1,line);
+	    continue;
+	};
+	let path = match kind {
+	    'l' => name.split_once(" -> ").map_or(name,|(p,_)| p),
+	    _ => name.strip_suffix(" -> ").or_else(|| name.strip_suffix(" ->")).unwrap_or(name)
+	};
+	// The first line is the directory find was started from
+	let path = match &root {
+	    None if kind == 'd' => {
+		root = Some(path.trim_end_matches('/').to_string());
+		continue;
+	    },
+	    None => path,
+	    Some(root) =>
+		match path.strip_prefix(root.as_str()) {
+		    Some(rest) if rest.starts_with('/') => rest,
+		    _ => {
+			warn!("Line {}: {:?} is not below {:?}",iline + 1,path,root);
+			continue;
+		    }
+		}
+	};
+	let kind = if kind == 'f' { '-' } else { kind };
+	builder.add(Path::new(path),kind,size,time,name)
+	    .map_err(|e| anyhow!("Line {}: {}",iline + 1,e))?;
+    }
+    Ok(root.filter(|r| r.starts_with('/')).map(|r| r.into()))
+}
+
+fn import_ls<R:BufRead>(input:R,builder:&mut Builder,now:i64)->Result<Option<OsString>> {
+    let mut root : Option<String> = None;
+    let mut dir = PathBuf::new();
+    let mut blank = true;
+    for (iline,line) in input.lines().enumerate() {
+	let line = line?;
+	if line.is_empty() {
+	    blank = true;
+	    continue;
+	}
+	let after_blank = std::mem::replace(&mut blank,false);
+	if line.starts_with("total ") {
+	    continue;
+	}
+	if let Some(e) = parse_long(&line,now) {
+	    let name = entry_name(&e);
+	    if name != "." && name != ".." {
+		builder.add(&dir.join(name),e.kind,e.size,e.time,e.name)
+		    .map_err(|e| anyhow!("Line {}: {}",iline + 1,e))?;
+	    }
+	} else if let Some(header) = line.strip_suffix(':').filter(|_| after_blank) {
+	    let header = header.trim_end_matches('/');
+	    dir = match &root {
+		None => {
+		    root = Some(header.to_string());
+		    PathBuf::new()
+		},
+		Some(root) =>
+		    match header.strip_prefix(root.as_str()) {
+			Some(rest) if rest.is_empty() || rest.starts_with('/') => rest.into(),
+			_ => bail!("Line {}: {:?} is not below {:?}",iline + 1,header,root)
+		    }
+	    };
+	} else {
+	    warn!("Line {}: cannot parse {:?}",iline + 1,line);
+	}
+    }
+    Ok(root.filter(|r| r.starts_with('/')).map(|r| r.into()))
+}
+
+fn import_tar<R:BufRead>(input:R,builder:&mut Builder,now:i64)->Result<()> {
+    for (iline,line) in input.lines().enumerate() {
+	let line = line?;
+	let Some(e) = parse_long(&line,now) else {
+	    warn!("Line {}: cannot parse {:?}",iline + 1,line);
+	    continue;
+	};
+	builder.add(Path::new(entry_name(&e)),e.kind,e.size,e.time,e.name)
+	    .map_err(|e| anyhow!("Line {}: {}",iline + 1,e))?;
+    }
+    Ok(())
+}
+
+/// Builds a map from a listing.  Unparsable lines are skipped with a
+/// warning.
+pub fn import<R:BufRead>(format:Format,input:R)->Result<FileSystem> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let mut builder = Builder::new(IMPORT_DEV);
+    let base =
+	match format {
+	    Format::FindPrintf => import_find(input,&mut builder)?,
+	    Format::LsR => import_ls(input,&mut builder,now)?,
+	    Format::TarTv => {
+		import_tar(input,&mut builder,now)?;
+		None
+	    },
+	    Format::Ncdu => return ncdu::import(input)
+	};
+    Ok(builder.finish(base))
+}
+
+#[test]
+fn test_parse_long() {
+    let now = unix_time(2024,6,1,(0,0)).unwrap();
+    let e = parse_long("-rw-r--r-- 1 bd users 1234 Mar  9  2019 a b.txt",now).unwrap();
+    assert_eq!(e,LongEntry { kind:'-',size:1234,time:unix_time(2019,3,9,(0,0)).unwrap(),name:"a b.txt" });
+    let e = parse_long("lrwxrwxrwx 1 bd users 7 Dec 31 23:59 l -> /etc",now).unwrap();
+    assert_eq!((e.time,entry_name(&e)),(unix_time(2023,12,31,(23,59)).unwrap(),"l"));
+    let e = parse_long("-rw-r--r-- bd/users 42 2019-03-09 12:30 etc/passwd",now).unwrap();
+    assert_eq!((e.size,e.time,e.name),(42,unix_time(2019,3,9,(12,30)).unwrap(),"etc/passwd"));
+    let e = parse_long("drwxr-xr-x 2 bd users 4096 2019-03-09 12:30:01.000000000 +0100 d",now).unwrap();
+    assert_eq!((e.kind,e.time,e.name),('d',unix_time(2019,3,9,(11,30)).unwrap(),"d"));
+    assert!(parse_long("total 12",now).is_none());
+}
+
+#[test]
+fn test_import() {
+    let fs = import(Format::LsR,"/mnt/old:
+total 8
+drwxr-xr-x 2 bd users 4096 Mar  9  2019 docs
+-rw-r--r-- 1 bd users   10 Mar  9  2019 a.txt
+
+/mnt/old/docs:
+total 4
+-rw-r--r-- 1 bd users   20 Mar  9  2019 b.txt
+".as_bytes()).unwrap();
+    assert_eq!(fs.base.as_deref(),Some(std::ffi::OsStr::new("/mnt/old")));
+    let names : Vec<&OsString> = fs.root.entries.iter().map(|(n,_)| n).collect();
+    assert_eq!(names,["a.txt","docs"]);
+    let Entry::Dir(docs) = &fs.root.entries[1].1 else { panic!() };
+    let &Entry::File(ino) = &docs.entries[0].1 else { panic!() };
+    assert_eq!(fs.mounts.get_device(IMPORT_DEV).unwrap().get_inode(ino).unwrap().size,20);
+
+    let fs = import(Format::FindPrintf,"d 4096 1552000000.5 /mnt/old
+f 10 1552000000.0 /mnt/old/a b.txt ->
+l 4 1552000000.0 /mnt/old/sub/l -> a b.txt
+".as_bytes()).unwrap();
+    let names : Vec<&OsString> = fs.root.entries.iter().map(|(n,_)| n).collect();
+    assert_eq!(names,["a b.txt","sub"]);
+    assert!(import(Format::FindPrintf,"d 4096 1552000000.0 /mnt/old
+d 4096 1552000000.0 /mnt/old/x
+f 10 1552000000.0 /mnt/old/x
+".as_bytes()).is_err());
+    assert!(import(Format::FindPrintf,"f 10 1552000000.0 /mnt/old/x
+f 10 1552000000.0 /mnt/old/x/y
+".as_bytes()).is_err());
+}
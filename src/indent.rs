@@ -1,3 +1,5 @@
+use std::io::{self,Write};
+
 pub enum IndentMode {
     #[allow(dead_code)]
     None,
@@ -6,15 +8,16 @@ pub enum IndentMode {
 }
 
 impl IndentMode {
-    pub fn put_indent(&self,indent:usize) {
+    pub fn put_indent<W:Write>(&self,out:&mut W,indent:usize)->io::Result<()> {
 	match self {
 	    IndentMode::None => (),
-	    IndentMode::Numbered => print!(" {:2} ",indent),
+	    IndentMode::Numbered => write!(out," {:2} ",indent)?,
 	    IndentMode::Spaces => {
 		for _ in 0..indent {
-		    print!("  ");
+		    write!(out,"  ")?;
 		}
 	    }
 	}
+	Ok(())
     }
 }
@@ -1,4 +1,5 @@
 use std::ffi::OsString;
+use std::io::Write;
 use anyhow::Result;
 use tz::{DateTime,TimeZoneRef};
 
@@ -8,60 +9,69 @@ use crate::{
     watcher::{Action,Watcher}
 };
 
-pub struct ListPrinter<'a> {
+pub struct ListPrinter<'a,W> {
     tz:TimeZoneRef<'a>,
-    long:bool
+    long:bool,
+    out:W
 }
 
-impl<'a> ListPrinter<'a> {
-    pub fn new(long:bool)->Self {
+impl<'a,W:Write> ListPrinter<'a,W> {
+    pub fn new(long:bool,out:W)->Self {
 	Self {
 	    tz:TimeZoneRef::utc(),
-	    long
+	    long,
+	    out
 	}
     }
 }
 
-impl<'a> Watcher for ListPrinter<'a> {
+impl<'a,W:Write> Watcher for ListPrinter<'a,W> {
     fn matching_entry(&mut self,
 		      _fse:&FileSystemEntry,
 		      _name:&OsString,
 		      device:&Device,
 		      entry:&Entry,
 		      data:&FsData)->Result<Action> {
-	print!("{}:{}",data.drive,data.path);
+	match &data.fse.label {
+	    Some(label) => write!(self.out,"{}:{}",label,data.path)?,
+	    None => write!(self.out,"{}:{}",data.drive,data.path)?
+	}
 	if self.long {
 	    match entry {
 		&Entry::Dir(_) => {
-		    print!("/");
+		    write!(self.out,"/")?;
 		},
-		&Entry::File(ino) => {
+		&Entry::File(ino) | &Entry::Archive(ino,_) => {
+		    if let Entry::Archive(..) = entry {
+			write!(self.out,"//")?;
+		    }
 		    if let Some(fi) = device.get_inode(ino) {
 			let dt = DateTime::from_timespec(
 			    fi.unix_time(),
 			    0,
 			    self.tz)?;
-			print!(" {} {:04}-{:02}-{:02}",
+			write!(self.out,
+			       " {} {:04}-{:02}-{:02}",
 			       fi.size,
 			       dt.year(),
 			       dt.month(),
-			       dt.month_day());
+			       dt.month_day())?;
 		    } else {
-			print!(" NO-INODE {}",ino);
+			write!(self.out," NO-INODE {}",ino)?;
 		    }
 		},
 		Entry::Symlink(sl) => {
-		    print!(" -> {:?}",sl);
+		    write!(self.out," -> {:?}",sl)?;
 		},
 		Entry::Other(ino) => {
-		    print!(" OTHER {}",ino);
+		    write!(self.out," OTHER {}",ino)?;
 		},
 		Entry::Error(err) => {
-		    print!(" ERROR {}",err);
+		    write!(self.out," ERROR {}",err)?;
 		},
 	    }
 	}
-	println!();
+	writeln!(self.out)?;
 	Ok(Action::Enter)
     }
 }
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path,PathBuf};
+use anyhow::{bail,Result};
+use log::warn;
+
+use crate::{
+    checksum::{file_digest,Digest},
+    fsexpr::FsData,
+    fsmap::*,
+    watcher::{Action,Watcher}
+};
+
+/// A local file to look for in the maps
+struct LocalFile {
+    path:PathBuf,
+    name:OsString,
+    // Computed when a copy with a digest is found
+    digest:Option<Option<Digest>>,
+    copies:Vec<Found>
+}
+
+/// An entry of a map that is a copy of a local file
+struct Found {
+    drive:String,
+    path:String,
+    // Whether the digests of both files were compared
+    verified:bool
+}
+
+/// Watcher looking for copies of local files, that is files with
+/// the same name and size, and the same digest if the map has one
+pub struct FileLocator {
+    files:Vec<LocalFile>,
+    by_size:HashMap<u64,Vec<usize>>
+}
+
+impl LocalFile {
+    fn digest(&mut self)->Option<Digest> {
+	let path = &self.path;
+	*self.digest.get_or_insert_with(|| {
+	    file_digest(path)
+		.map_err(|e| warn!("Cannot compute the digest of {:?}: {}",path,e))
+		.ok()
+	})
+    }
+}
+
+impl FileLocator {
+    pub fn new()->Self {
+	Self { files:Vec::new(),by_size:HashMap::new() }
+    }
+
+    /// Adds the file at path, or the regular files below it if it is
+    /// a directory
+    pub fn add<P:AsRef<Path>>(&mut self,path:P)->Result<()> {
+	let path = path.as_ref();
+	let md = path.symlink_metadata()?;
+	if md.is_dir() {
+	    let mut entries : Vec<PathBuf> = fs::read_dir(path)?
+		.filter_map(|e| match e {
+		    Ok(e) => Some(e.path()),
+		    Err(e) => {
+			warn!("Cannot read {:?}: {}",path,e);
+			None
+		    }
+		})
+		.collect();
+	    entries.sort();
+	    for sub_path in entries {
+		if let Err(e) = self.add(&sub_path) {
+		    warn!("Cannot read {:?}: {}",sub_path,e);
+		}
+	    }
+	} else if md.is_file() {
+	    let Some(name) = path.file_name() else { bail!("No file name in {:?}",path) };
+	    self.by_size.entry(md.size()).or_default().push(self.files.len());
+	    self.files.push(LocalFile {
+		path:path.to_path_buf(),
+		name:name.to_os_string(),
+		digest:None,
+		copies:Vec::new()
+	    });
+	}
+	Ok(())
+    }
+
+    pub fn is_empty(&self)->bool {
+	self.files.is_empty()
+    }
+
+    /// Writes the copies of each file, returning the number of files
+    /// without any copy
+    pub fn write_copies<W:Write>(&self,out:&mut W)->Result<usize> {
+	let mut missing = 0;
+	let indent = if self.files.len() > 1 { "  " } else { "" };
+	for file in self.files.iter() {
+	    if self.files.len() > 1 {
+		writeln!(out,"{}:",file.path.display())?;
+	    }
+	    if file.copies.is_empty() {
+		missing += 1;
+		writeln!(out,"{}Not found",indent)?;
+	    }
+	    for copy in file.copies.iter() {
+		writeln!(out,"{}{}:{}{}",
+			 indent,
+			 copy.drive,
+			 copy.path,
+			 if copy.verified { " (same digest)" } else { "" })?;
+	    }
+	}
+	Ok(missing)
+    }
+
+    /// Writes the files that have no copy, returning their number
+    pub fn write_missing<W:Write>(&self,out:&mut W)->Result<usize> {
+	let mut missing = 0;
+	for file in self.files.iter().filter(|f| f.copies.is_empty()) {
+	    missing += 1;
+	    writeln!(out,"{}",file.path.display())?;
+	}
+	writeln!(out,"{} of {} files missing from every map",missing,self.files.len())?;
+	Ok(missing)
+    }
+}
+
+impl Watcher for FileLocator {
+    fn matching_entry(&mut self,
+		      _fse:&FileSystemEntry,
+		      name:&OsString,
+		      device:&Device,
+		      entry:&Entry,
+		      data:&FsData)->Result<Action> {
+	let (&Entry::File(ino) | &Entry::Archive(ino,_)) = entry else { return Ok(Action::Enter) };
+	let Some(fi) = device.get_inode(ino) else { return Ok(Action::Enter) };
+	let Some(indices) = self.by_size.get(&fi.size) else { return Ok(Action::Enter) };
+	for &i in indices.iter() {
+	    let file = &mut self.files[i];
+	    if file.name != *name {
+		continue;
+	    }
+	    let verified =
+		match fi.digest {
+		    Some(digest) => match file.digest() {
+			Some(local) if local != digest => continue,
+			Some(_) => true,
+			None => false
+		    },
+		    None => false
+		};
+	    file.copies.push(Found {
+		drive:data.fse.label.clone().unwrap_or_else(|| data.drive.to_string()),
+		path:data.path.to_string(),
+		verified
+	    });
+	}
+	Ok(Action::Enter)
+    }
+}
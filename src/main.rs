@@ -1,61 +1,187 @@
-use anyhow::{Result,bail};
+use anyhow::{anyhow,bail,Result};
 use pico_args::Arguments;
 use std::ffi::OsString;
 use std::path::{Path,PathBuf};
 use log::{self,info,warn,LevelFilter};
 use rustyline as rl;
 
+mod archive;
 mod basic_printer;
 mod boolean;
+mod catalog;
+mod checksum;
 mod counter;
+mod coverage;
+mod diff;
 mod dumper;
 mod examiner_cli;
+mod examiner_helper;
+mod export;
+mod extract;
 mod fsexpr;
 mod fsparser;
 mod fstok;
 mod fsmap;
 mod help;
+mod image;
+mod import;
 mod indent;
 mod limiter;
 mod list_printer;
+mod locate_file;
+mod merge;
+mod mtree;
+mod ncdu;
+mod output;
+mod query;
+mod resolver;
 mod scanner;
 mod sigint_detector;
+mod sqlite;
+mod stats;
+mod timeline;
+mod types;
 mod valve;
+mod variables;
 mod watcher;
 
 use basic_printer::BasicPrinter;
+use catalog::{Catalog,CatalogEntry};
 use fsexpr::FsExpr;
 use fsmap::*;
+use locate_file::FileLocator;
+use merge::{Merger,Policy};
 use examiner_cli::ExaminerCli;
+use examiner_helper::ExaminerHelper;
+use extract::Extractor;
 use counter::Counter;
+use coverage::{Coverage,Remap};
 use dumper::Dumper;
+use query::Query;
 use scanner::Scanner;
 use sigint_detector::SigintDetector;
+use stats::Stats;
+use timeline::{Period,Timeline,TimelineSettings};
+use types::{Categories,Grouping,TypeReport};
+use watcher::Watcher;
+
+fn scan_tree(mut scanner:Scanner<Counter>,path:&Path)->Result<FileSystem> {
+    let mut mounts = Mounts::new();
+    match scanner.scan(&mut mounts,path)? {
+	Entry::Dir(root) =>
+	    Ok(FileSystem{
+		mounts,
+		root,
+		base:Some(path.canonicalize()?.into_os_string())
+	    }),
+	_ => bail!("Not a directory")
+    }
+}
 
 fn collect(mut args:Arguments)->Result<()> {
     let out : OsString = args.value_from_str("--out")?;
     let one_device : bool = args.contains("--one-device");
+    let digest : bool = args.contains("--digest");
+    let descend_archives : bool = args.contains("--descend-archives");
+    let from_image : bool = args.contains("--image");
     let paths : Vec<OsString> = args.finish();
     if paths.len() != 1 {
 	bail!("Exactly one path must be given to collect");
     }
     let path = Path::new(&paths[0]);
-    let mut mounts = Mounts::new();
-    let counter = Counter::new();
-    let mut scanner = Scanner::new(counter,one_device);
+    if from_image {
+	if digest || descend_archives {
+	    bail!("--digest and --descend-archives cannot be used with --image");
+	}
+	let fs = image::read_image(path)
+	    .map_err(|e| anyhow!("Cannot read image {:?}: {}",path,e))?;
+	fs.save_to_file(out)?;
+	return Ok(());
+    }
+    let mut scanner = Scanner::new(Counter::new(),one_device,digest);
+    scanner.set_descend_archives(descend_archives);
+    let fs = scan_tree(scanner,path)?;
+    fs.save_to_file(out)?;
+    Ok(())
+}
+
+fn import(mut args:Arguments)->Result<()> {
+    let out : OsString = args.value_from_str("--out")?;
+    let from : String = args.value_from_str("--from")?;
+    let format = import::Format::parse(&from)?;
+    let inputs = args.finish();
     let fs =
-	match scanner.scan(&mut mounts,path)? {
-	    Entry::Dir(root) =>
-		FileSystem{
-		    mounts,
-		    root
-		},
-	    _ => bail!("Not a directory")
+	match &inputs[..] {
+	    [] => import::import(format,std::io::stdin().lock())?,
+	    [path] => {
+		let fd = std::fs::File::open(path)
+		    .map_err(|e| anyhow!("Cannot open {:?}: {}",path,e))?;
+		import::import(format,std::io::BufReader::new(fd))?
+	    },
+	    _ => bail!("At most one listing can be imported")
 	};
     fs.save_to_file(out)?;
     Ok(())
 }
 
+fn export(mut args:Arguments)->Result<()> {
+    let format : String = args.value_from_str("--format")?;
+    let format = export::Format::parse(&format)?;
+    let out : Option<PathBuf> = args.opt_value_from_str("--out")?;
+    let load_catalog = args.contains("--catalog");
+    let fss = load_labelled(args.finish(),load_catalog)?;
+    export::export(&fss,format,out.as_deref())
+}
+
+// Returns the number of differences
+fn verify_changes(mut args:Arguments)->Result<usize> {
+    let spec : Option<PathBuf> = args.opt_value_from_str("--mtree")?;
+    let ignore_time = args.contains("--ignore-time");
+    let one_device = args.contains("--one-device");
+    let inputs = args.finish();
+    let load = |path:&OsString| FileSystem::from_file(path)
+	.map_err(|e| anyhow!("Error loading {:?}: {}",path,e));
+    let (expected,actual,mut options) =
+	match (spec,&inputs[..]) {
+	    (Some(spec),[map]) => {
+		let fd = std::fs::File::open(&spec)
+		    .map_err(|e| anyhow!("Cannot open {:?}: {}",spec,e))?;
+		let (expected,options) = mtree::parse(std::io::BufReader::new(fd))?;
+		(expected,load(map)?,options)
+	    },
+	    (None,[map,rest @ ..]) if rest.len() <= 1 => {
+		let expected = load(map)?;
+		let path = match (rest.first(),&expected.base) {
+		    (Some(path),_) | (None,Some(path)) => PathBuf::from(path),
+		    (None,None) => bail!("Give the directory to verify, {:?} does not record it",map)
+		};
+		// Contents are compared only if the map has digests
+		let digest = expected.has_digests();
+		let counter = Counter::with_output(Box::new(std::io::stderr()));
+		let actual = scan_tree(Scanner::new(counter,one_device,digest),&path)?;
+		(expected,actual,diff::Options::default())
+	    },
+	    _ => bail!("Usage: verify [--ignore-time] [--one-device] MAP [PATH] \
+			or verify [--ignore-time] --mtree SPEC MAP")
+	};
+    options.ignore_time |= ignore_time;
+    let changes = diff::diff(&expected,&actual,&options);
+    diff::write_changes(&mut std::io::stdout().lock(),&changes)?;
+    Ok(changes.len())
+}
+
+// Exits with 0 if there are no differences, 1 if there are some and
+// 2 on errors
+fn verify(args:Arguments)->Result<()> {
+    match verify_changes(args) {
+	Ok(n) => std::process::exit(if n > 0 { 1 } else { 0 }),
+	Err(e) => {
+	    eprintln!("Error: {}",e);
+	    std::process::exit(2)
+	}
+    }
+}
+
 fn help(_args:Arguments)->Result<()> {
     print!("{}",help::COMMAND_TEXT);
     Ok(())
@@ -70,44 +196,273 @@ fn dump(mut args:Arguments)->Result<()> {
     let expr : String = args.opt_value_from_str("--expr")?
 	.unwrap_or_else(|| "%t".to_string());
     let expr = FsExpr::parse(&expr)?;
-    let inputs = args.finish();
-    let (fss,errs) = FileSystems::load_multiple(&inputs[..]);
+    let query = Query::compile(&expr)?;
+    let load_catalog = args.contains("--catalog");
+    let fss = load_labelled(args.finish(),load_catalog)?;
+    let sd = SigintDetector::new();
+    let bp = BasicPrinter::new(std::io::stdout());
+    let mut dp = Dumper::new(&sd,&fss,&query,bp);
+    dp.dump()?;
+    Ok(())
+}
+
+// Runs watcher over the entries of the maps given as arguments that
+// match the expression of --expr
+fn aggregate<W:Watcher>(mut args:Arguments,watcher:W)->Result<W> {
+    let expr : String = args.opt_value_from_str("--expr")?
+	.unwrap_or_else(|| "%t".to_string());
+    let expr = FsExpr::parse(&expr)?;
+    let query = Query::compile(&expr)?;
+    let load_catalog = args.contains("--catalog");
+    let fss = load_labelled(args.finish(),load_catalog)?;
+    let sd = SigintDetector::new();
+    let mut dp = Dumper::new(&sd,&fss,&query,watcher);
+    dp.dump()?;
+    Ok(dp.into_inner())
+}
+
+fn stats(args:Arguments)->Result<()> {
+    aggregate(args,Stats::new())?.write(&mut std::io::stdout())
+}
+
+fn types(mut args:Arguments)->Result<()> {
+    let grouping =
+	if args.contains("--categories") {
+	    Grouping::Category(Categories::load(Categories::default_path())?)
+	} else {
+	    Grouping::Extension
+	};
+    aggregate(args,TypeReport::new(grouping))?.write(&mut std::io::stdout())
+}
+
+fn timeline(mut args:Arguments)->Result<()> {
+    let settings = TimelineSettings {
+	period:if args.contains("--monthly") { Period::Month } else { Period::Year },
+	csv:args.contains("--csv"),
+	by_drive:args.contains("--by-drive")
+    };
+    aggregate(args,Timeline::new(settings))?.write(&mut std::io::stdout())
+}
+
+// Loads the maps, and those of the catalog if load_catalog is set,
+// labelling them with their catalog names.  A catalog that cannot be
+// read is only an error when its maps are asked for.
+fn load_labelled(mut inputs:Vec<OsString>,load_catalog:bool)->Result<FileSystems> {
+    let catalog =
+	match Catalog::load(Catalog::default_path()) {
+	    Ok(catalog) => Some(catalog),
+	    Err(e) if load_catalog => return Err(e),
+	    Err(e) => {
+		warn!("Error loading the catalog: {}",e);
+		None
+	    }
+	};
+    if let Some(catalog) = catalog.as_ref().filter(|_| load_catalog) {
+	inputs.extend(catalog.entries.iter().map(|e| catalog.map_path(e).into_os_string()));
+    }
+    if inputs.is_empty() {
+	bail!("No maps given");
+    }
+    let (mut fss,errs) = FileSystems::load_multiple(&inputs[..]);
     for (path,err) in &errs {
 	warn!("Error loading {:?}: {}",path,err);
     }
+    let Some(catalog) = catalog else { return Ok(fss) };
+    for idrive in 0..fss.systems.len() {
+	if let Some(entry) = catalog.find_map(&fss.systems[idrive].origin) {
+	    let _ = fss.set_label(idrive,Some(entry.name.clone()));
+	}
+    }
+    Ok(fss)
+}
+
+fn locate_file(mut args:Arguments)->Result<()> {
+    let dir_mode = args.contains("--dir");
+    let load_catalog = args.contains("--catalog");
+    let mut inputs = args.finish();
+    if inputs.is_empty() {
+	bail!("Usage: locate-file [--dir] [--catalog] PATH MAP1 ... MAPN");
+    }
+    let path = PathBuf::from(inputs.remove(0));
+    let mut locator = FileLocator::new();
+    if !dir_mode && path.is_dir() {
+	bail!("{:?} is a directory, use --dir",path);
+    }
+    locator.add(&path)?;
+    if locator.is_empty() {
+	bail!("No files found in {:?}",path);
+    }
+    let fss = load_labelled(inputs,load_catalog)?;
+    let expr = FsExpr::parse("%t")?;
+    let query = Query::compile(&expr)?;
+    let sd = SigintDetector::new();
+    let mut dp = Dumper::new(&sd,&fss,&query,locator);
+    dp.dump()?;
+    let locator = dp.into_inner();
+    let mut out = std::io::stdout();
+    let missing =
+	if dir_mode {
+	    locator.write_missing(&mut out)?
+	} else {
+	    locator.write_copies(&mut out)?
+	};
+    std::process::exit(if missing > 0 { 1 } else { 0 })
+}
+
+fn coverage(mut args:Arguments)->Result<()> {
+    let min_copies : usize = args.opt_value_from_str("--min-copies")?.unwrap_or(2);
+    let remaps : Vec<String> = args.values_from_str("--remap")?;
+    let remaps = remaps.iter().map(|u| Remap::parse(u)).collect::<Result<Vec<Remap>>>()?;
+    let list = args.contains("--list");
+    let load_catalog = args.contains("--catalog");
+    let expr : String = args.opt_value_from_str("--expr")?
+	.unwrap_or_else(|| "%t".to_string());
+    let expr = FsExpr::parse(&expr)?;
+    let query = Query::compile(&expr)?;
+    let fss = load_labelled(args.finish(),load_catalog)?;
+    let sd = SigintDetector::new();
+    let mut dp = Dumper::new(&sd,&fss,&query,Coverage::new(min_copies,remaps));
+    dp.dump()?;
+    dp.into_inner().write(&mut std::io::stdout(),list)?;
+    Ok(())
+}
+
+fn merge(mut args:Arguments)->Result<()> {
+    let out : OsString = args.value_from_str("--out")?;
+    let policy = match args.opt_value_from_str::<_,String>("--policy")? {
+	Some(u) => Policy::parse(&u)?,
+	None => Policy::Newest
+    };
+    let at : Vec<PathBuf> = args.values_from_str("--at")?;
+    let inputs = args.finish();
+    if inputs.is_empty() {
+	bail!("No maps given");
+    }
+    if !at.is_empty() && at.len() != inputs.len() {
+	bail!("Give --at once for each map, or not at all");
+    }
+    let mut systems = Vec::new();
+    for path in inputs.iter() {
+	let fs = FileSystem::from_file(path)
+	    .map_err(|e| anyhow!("Error loading {:?}: {}",path,e))?;
+	systems.push(fs);
+    }
+    // Without --at, maps are placed according to the directories
+    // they were scanned from
+    let bases : Option<Vec<&Path>> = systems.iter()
+	.map(|fs| fs.base.as_ref().map(Path::new))
+	.collect();
+    let (base,at) =
+	match bases {
+	    Some(bases) if at.is_empty() => {
+		let (common,points) = merge::mount_points(&bases);
+		(Some(common.into_os_string()),points)
+	    },
+	    _ if at.is_empty() => (None,vec![PathBuf::new();systems.len()]),
+	    _ => (None,at)
+	};
+    let mut merger = Merger::new(policy);
+    for ((fs,at),path) in systems.into_iter().zip(at.iter()).zip(inputs.iter()) {
+	info!("Grafting {:?} at /{}",path,at.display());
+	merger.add(fs,at)
+	    .map_err(|e| anyhow!("Error merging {:?}: {}",path,e))?;
+    }
+    merger.finish(base)?.save_to_file(out)?;
+    Ok(())
+}
+
+fn extract(mut args:Arguments)->Result<()> {
+    let out : OsString = args.value_from_str("--out")?;
+    let root : PathBuf = args.opt_value_from_str("--root")?.unwrap_or_default();
+    let expr : String = args.opt_value_from_str("--expr")?
+	.unwrap_or_else(|| "%t".to_string());
+    let expr = FsExpr::parse(&expr)?;
+    let query = Query::compile(&expr)?;
+    let inputs = args.finish();
+    if inputs.len() != 1 {
+	bail!("Exactly one map must be given to extract");
+    }
+    let fss = FileSystems { systems:vec![FileSystemEntry::load(&inputs[0])?] };
+    let fs = &fss.systems[0].fs;
+    let (dir,depth) = extract::find_dir(fs,&root)?;
     let sd = SigintDetector::new();
-    let bp = BasicPrinter::new();
-    let mut dp = Dumper::new(&sd,&fss,&expr,bp);
+    let mut dp = Dumper::new(&sd,&fss,&query,Extractor::new(depth,dir.dev));
+    if depth > 0 {
+	dp.set_scope(0,root.clone());
+    }
     dp.dump()?;
+    let rel : PathBuf = root.components()
+	.filter(|c| matches!(c,std::path::Component::Normal(_)))
+	.collect();
+    let base = fs.base.as_ref().map(|base| Path::new(base).join(rel).into_os_string());
+    dp.into_inner().finish(fs,base).save_to_file(out)?;
     Ok(())
 }
 
 fn examine(mut args:Arguments)->Result<()> {
     info!("Loading inputs");
     let enable_history = !args.contains("--no-history");
+    let enable_rc = !args.contains("--no-rc");
+    let strict = args.contains("--strict");
+    let script : Option<OsString> = args.opt_value_from_str("--script")?;
+    let commands : Option<String> = args.opt_value_from_str("-c")?;
+    let load_catalog = args.contains("--catalog");
     let inputs = args.finish();
     let (fss,errs) = FileSystems::load_multiple(&inputs[..]);
     for (path,err) in &errs {
 	warn!("Error loading {:?}: {}",path,err);
     }
     let mut cli = ExaminerCli::new(fss);
+    match Catalog::load(Catalog::default_path()) {
+	Ok(catalog) => cli.set_catalog(catalog),
+	Err(e) => warn!("Error loading the catalog: {}",e)
+    }
+    if load_catalog {
+	cli.load_catalog_maps();
+    }
+
+    let mut home = PathBuf::new();
+    if let Some(dir) = std::env::var_os("HOME") {
+	home.push(dir);
+    }
+    if enable_rc {
+	if let Err(e) = cli.load_rc(home.join(".fsmaprc")) {
+	    warn!("Error loading ~/.fsmaprc: {}",e);
+	}
+    }
+
+    if script.is_some() || commands.is_some() {
+	let mut lines = Vec::new();
+	if let Some(path) = script {
+	    let text = std::fs::read_to_string(&path)
+		.map_err(|e| anyhow!("Cannot read script {:?}: {}",path,e))?;
+	    lines.extend(text.lines().map(|u| u.to_string()));
+	}
+	if let Some(u) = commands {
+	    lines.push(u);
+	}
+	cli.set_pager(false);
+	let failures = cli.run_batch(&lines,strict);
+	std::process::exit(if failures > 0 { 1 } else { 0 })
+    }
 
     let config = rl::config::Config::builder()
 	.auto_add_history(true)
+	.completion_type(rl::CompletionType::List)
 	.build();
-    let mut rl : rl::Editor<(),rl::history::FileHistory> =
+    let mut rl : rl::Editor<ExaminerHelper,rl::history::FileHistory> =
 	rl::Editor::with_config(config)?;
-    let mut hist_path = PathBuf::new();
-    if let Some(home) = std::env::var_os("HOME") {
-	hist_path.push(home);
-    }
-    hist_path.push(".fsmap-hist");
+    let hist_path = home.join(".fsmap-hist");
     if enable_history {
 	let _ = rl.load_history(&hist_path);
     }
+    rl.set_helper(Some(ExaminerHelper::new(cli)));
 
     loop {
-	match rl.readline("> ") {
+	let prompt = rl.helper().map(|h| h.cli.prompt()).unwrap_or_default();
+	let line = rl.readline(&prompt);
+	let cli = &mut rl.helper_mut().unwrap().cli;
+	match line {
 	    Err(rl::error::ReadlineError::Eof) => break,
 	    Err(rl::error::ReadlineError::Interrupted) => println!("^C"),
 	    Err(e) => eprintln!("Error: {}",e),
@@ -125,6 +480,48 @@ fn examine(mut args:Arguments)->Result<()> {
     std::process::exit(0)
 }
 
+fn catalog(mut args:Arguments)->Result<()> {
+    let action = args.subcommand()?.unwrap_or_else(|| "list".to_string());
+    let label : Option<String> = args.opt_value_from_str("--label")?;
+    let serial : Option<String> = args.opt_value_from_str("--serial")?;
+    let description : Option<String> = args.opt_value_from_str("--description")?;
+    let rest : Vec<String> = args.finish().iter()
+	.map(|u| u.to_string_lossy().into_owned())
+	.collect();
+    let mut catalog = Catalog::load(Catalog::default_path())?;
+    match (action.as_str(),&rest[..]) {
+	("list",[]) => {
+	    for entry in catalog.entries.iter() {
+		println!("{:16} {}",entry.name,catalog.map_path(entry).display());
+		for (key,value) in [("Label",&entry.label),
+				    ("Serial",&entry.serial),
+				    ("Description",&entry.description)] {
+		    if let Some(value) = value {
+			println!("{:16}   {}: {}","",key,value);
+		    }
+		}
+	    }
+	},
+	("add",[name,map]) => {
+	    catalog.add(CatalogEntry {
+		name:name.clone(),
+		map:Path::new(map).canonicalize()?,
+		label,
+		serial,
+		description
+	    })?;
+	    catalog.save()?;
+	},
+	("remove",[name]) => {
+	    catalog.remove(name)?;
+	    catalog.save()?;
+	},
+	_ => bail!("Usage: catalog [list | add NAME MAP [--label L] [--serial S] \
+		    [--description D] | remove NAME]")
+    }
+    Ok(())
+}
+
 fn main()->Result<()> {
     let mut args = Arguments::from_env();
     env_logger::Builder::new()
@@ -135,11 +532,22 @@ fn main()->Result<()> {
     type Command = Box<dyn Fn(Arguments)->Result<()>>;
 
     let cmds : &[(&str,Command)] = &[
+	("catalog",Box::new(catalog)),
 	("collect",Box::new(collect)),
+	("coverage",Box::new(coverage)),
 	("dump",Box::new(dump)),
 	("examine",Box::new(examine)),
+	("export",Box::new(export)),
+	("extract",Box::new(extract)),
 	("help",Box::new(help)),
-	("help-expr",Box::new(help_expr))
+	("help-expr",Box::new(help_expr)),
+	("import",Box::new(import)),
+	("locate-file",Box::new(locate_file)),
+	("merge",Box::new(merge)),
+	("stats",Box::new(stats)),
+	("timeline",Box::new(timeline)),
+	("types",Box::new(types)),
+	("verify",Box::new(verify))
     ];
 
     match args.subcommand()?
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Component,Path,PathBuf};
+use anyhow::{bail,Result};
+
+use crate::fsmap::*;
+
+/// What to do when two maps have different entries at the same path
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Policy {
+    // Keep the file with the newest time stamp, or the entry from the
+    // map given last if times cannot be compared
+    Newest,
+    Error
+}
+
+/// Grafts the trees of several maps into a single one
+pub struct Merger {
+    policy:Policy,
+    mounts:Mounts,
+    root:Option<Directory>,
+    // Inodes copied into the table of another device, by source
+    // device, source inode and target device
+    moved:HashMap<(u64,u64,u64),u64>
+}
+
+impl Policy {
+    pub fn parse(u:&str)->Result<Self> {
+	match u {
+	    "newest" => Ok(Self::Newest),
+	    "error" => Ok(Self::Error),
+	    _ => bail!("Unknown policy {:?}, expected newest or error",u)
+	}
+    }
+}
+
+/// Common base directory of maps scanned at the given absolute paths,
+/// and where each of them goes below it
+pub fn mount_points(bases:&[&Path])->(PathBuf,Vec<PathBuf>) {
+    let mut common = bases.first().map(|p| p.to_path_buf()).unwrap_or_default();
+    for base in bases.iter() {
+	while !base.starts_with(&common) {
+	    if !common.pop() {
+		break;
+	    }
+	}
+    }
+    let points = bases.iter()
+	.map(|base| base.strip_prefix(&common).unwrap_or(base).to_path_buf())
+	.collect();
+    (common,points)
+}
+
+impl Merger {
+    pub fn new(policy:Policy)->Self {
+	Self {
+	    policy,
+	    mounts:Mounts::new(),
+	    root:None,
+	    moved:HashMap::new()
+	}
+    }
+
+    /// Grafts the tree of fs at path at, renumbering devices whose
+    /// number is already taken
+    pub fn add(&mut self,fs:FileSystem,at:&Path)->Result<()> {
+	let mut renumber = HashMap::new();
+	let mut next = self.mounts.devices.keys().max().map(|&d| d + 1).unwrap_or(0);
+	for (dev,device) in fs.mounts.devices {
+	    let new_dev =
+		if self.mounts.devices.contains_key(&dev) {
+		    while self.mounts.devices.contains_key(&next) || renumber.contains_key(&next) {
+			next += 1;
+		    }
+		    next
+		} else {
+		    dev
+		};
+	    renumber.insert(dev,new_dev);
+	    self.mounts.devices.insert(new_dev,device);
+	}
+	let mut src = fs.root;
+	Self::renumber(&mut src,&renumber);
+
+	let names : Vec<OsString> = at.components()
+	    .filter_map(|c| match c {
+		Component::Normal(u) => Some(u.to_os_string()),
+		_ => None
+	    })
+	    .collect();
+	let mut root = match self.root.take() {
+	    None if names.is_empty() => {
+		self.root = Some(src);
+		return Ok(());
+	    },
+	    None => Directory::new(src.dev),
+	    Some(root) => root
+	};
+	let res = self.graft(&mut root,src,&names,&mut PathBuf::new());
+	self.root = Some(root);
+	res
+    }
+
+    pub fn finish(self,base:Option<OsString>)->Result<FileSystem> {
+	let Some(root) = self.root else { bail!("No maps to merge") };
+	Ok(FileSystem { mounts:self.mounts,root,base })
+    }
+
+    fn renumber(dir:&mut Directory,renumber:&HashMap<u64,u64>) {
+	if let Some(&dev) = renumber.get(&dir.dev) {
+	    dir.dev = dev;
+	}
+	for (_,entry) in dir.entries.iter_mut() {
+	    if let Some(sub) = entry.as_dir_mut() {
+		Self::renumber(sub,renumber);
+	    }
+	}
+    }
+
+    fn graft(&mut self,
+	     dir:&mut Directory,
+	     src:Directory,
+	     names:&[OsString],
+	     path:&mut PathBuf)->Result<()> {
+	let Some((first,rest)) = names.split_first() else {
+	    return self.merge_dir(dir,src,path);
+	};
+	path.push(first);
+	let i = match dir.entries.iter().position(|(n,_)| n == first) {
+	    Some(i) => i,
+	    None => {
+		dir.insert(first.clone(),Entry::Dir(Directory::new(src.dev)));
+		dir.entries.len() - 1
+	    }
+	};
+	let Entry::Dir(sub) = &mut dir.entries[i].1 else {
+	    bail!("Cannot graft below {:?}, which is not a directory",path);
+	};
+	self.graft(sub,src,rest,path)?;
+	path.pop();
+	Ok(())
+    }
+
+    fn merge_dir(&mut self,dir:&mut Directory,src:Directory,path:&mut PathBuf)->Result<()> {
+	let mut index : HashMap<OsString,usize> = dir.entries.iter()
+	    .enumerate()
+	    .map(|(i,(name,_))| (name.clone(),i))
+	    .collect();
+	for (name,entry) in src.entries {
+	    path.push(&name);
+	    match index.get(&name) {
+		None => {
+		    let entry = self.adopt(entry,src.dev,dir.dev);
+		    index.insert(name.clone(),dir.entries.len());
+		    dir.insert(name,entry);
+		},
+		Some(&i) => {
+		    let dev = dir.dev;
+		    match (&mut dir.entries[i].1,entry) {
+			(Entry::Dir(sub),Entry::Dir(src_sub)) => self.merge_dir(sub,src_sub,path)?,
+			(old,entry) =>
+			    if self.replaces(old,dev,&entry,src.dev,path)? {
+				*old = self.adopt(entry,src.dev,dev);
+			    }
+		    }
+		}
+	    }
+	    path.pop();
+	}
+	Ok(())
+    }
+
+    fn info(&self,dev:u64,entry:&Entry)->Option<&FileInfo> {
+	match entry {
+	    &Entry::File(ino) | &Entry::Other(ino) | &Entry::Archive(ino,_) =>
+		self.mounts.get_device(dev)?.get_inode(ino),
+	    _ => None
+	}
+    }
+
+    // Decides whether entry from the map being added replaces the
+    // existing one
+    fn replaces(&self,old:&Entry,old_dev:u64,new:&Entry,new_dev:u64,path:&Path)->Result<bool> {
+	let (old_info,new_info) = (self.info(old_dev,old),self.info(new_dev,new));
+	let same =
+	    match (old,new) {
+		(Entry::File(_),Entry::File(_)) |
+		(Entry::Other(_),Entry::Other(_)) |
+		(Entry::Archive(..),Entry::Archive(..)) =>
+		    matches!((old_info,new_info),
+			     (Some(a),Some(b)) if a.size == b.size && a.time == b.time),
+		(Entry::Symlink(a),Entry::Symlink(b)) => a == b,
+		// Entries that could not be scanned in one of the maps
+		(_,Entry::Error(_)) => true,
+		_ => false
+	    };
+	if same {
+	    return Ok(false);
+	}
+	if let Entry::Error(_) = old {
+	    return Ok(true);
+	}
+	// Whatever the policy, a file must not replace a whole directory
+	if old.kind_name() != new.kind_name() {
+	    bail!("Conflicting types at {:?}: {} and {}",path,old.kind_name(),new.kind_name());
+	}
+	match (self.policy,old_info,new_info) {
+	    (Policy::Error,_,_) => bail!("Conflicting entries at {:?}",path),
+	    (Policy::Newest,Some(a),Some(b)) => Ok(b.time >= a.time),
+	    (Policy::Newest,_,_) => Ok(true)
+	}
+    }
+
+    // Moves entry from a directory of device from to one of device to,
+    // copying its inode if the devices differ
+    fn adopt(&mut self,entry:Entry,from:u64,to:u64)->Entry {
+	if from == to {
+	    return entry;
+	}
+	let ino = match entry {
+	    Entry::File(ino) | Entry::Other(ino) | Entry::Archive(ino,_) => ino,
+	    _ => return entry
+	};
+	let Some(fi) = self.mounts.get_device(from).and_then(|d| d.get_inode(ino)).cloned() else {
+	    return entry
+	};
+	let new_ino = *self.moved.entry((from,ino,to)).or_insert_with(|| {
+	    let device = self.mounts.get_device_mut(to).unwrap();
+	    let new_ino =
+		if device.has_inode(ino) {
+		    device.inodes.keys().max().map(|&i| i + 1).unwrap_or(0)
+		} else {
+		    ino
+		};
+	    device.insert_inode(new_ino,fi);
+	    new_ino
+	});
+	match entry {
+	    Entry::File(_) => Entry::File(new_ino),
+	    Entry::Archive(_,members) => Entry::Archive(new_ino,members),
+	    _ => Entry::Other(new_ino)
+	}
+    }
+}
+
+#[test]
+fn test_merge() {
+    let (common,points) = mount_points(&[Path::new("/vol/a"),Path::new("/vol/b/c")]);
+    assert_eq!(common,Path::new("/vol"));
+    assert_eq!(points,vec![PathBuf::from("a"),PathBuf::from("b/c")]);
+
+    let map = |files:&[(&str,u64,u64,i32)]| {
+	let mut mounts = Mounts::new();
+	let mut root = Directory::new(5);
+	let mut sub = Directory::new(5);
+	for &(name,ino,size,time) in files {
+	    mounts.get_device_mut(5).unwrap()
+		.insert_inode(ino,FileInfo { size,time,digest:None,mtime:None });
+	    match name.strip_prefix("d/") {
+		Some(name) => sub.insert(name.into(),Entry::File(ino)),
+		None => root.insert(name.into(),Entry::File(ino))
+	    }
+	}
+	root.insert("d".into(),Entry::Dir(sub));
+	FileSystem { mounts,root,base:None }
+    };
+    let a = || map(&[("a",1,1,10),("d/x",2,3,10)]);
+    let b = || map(&[("a",1,2,20),("d/y",2,4,10)]);
+    let mut merger = Merger::new(Policy::Newest);
+    merger.add(a(),Path::new("")).unwrap();
+    merger.add(b(),Path::new("/")).unwrap();
+    merger.add(b(),Path::new("/e")).unwrap();
+    let fs = merger.finish(None).unwrap();
+    assert_eq!(fs.mounts.devices.len(),3);
+    let size = |dir:&Directory,name:&str| {
+	dir.entries.iter().find(|(n,_)| n == name).and_then(|(_,e)| match e {
+	    &Entry::File(ino) => Some(fs.mounts.get_device(dir.dev)?.get_inode(ino)?.size),
+	    _ => None
+	})
+    };
+    let Some((_,Entry::Dir(d))) = fs.root.entries.iter().find(|(n,_)| n == "d") else { panic!() };
+    assert_eq!(size(&fs.root,"a"),Some(2));
+    assert_eq!(size(d,"x"),Some(3));
+    assert_eq!(size(d,"y"),Some(4));
+    assert_eq!(fs.root.entries.len(),3);
+    let mut c = a();
+    c.root.entries[0].1 = Entry::Dir(Directory::new(5));
+    let mut merger = Merger::new(Policy::Newest);
+    merger.add(a(),Path::new("")).unwrap();
+    assert!(merger.add(c,Path::new("")).is_err());
+
+    let mut merger = Merger::new(Policy::Error);
+    merger.add(a(),Path::new("")).unwrap();
+    merger.add(a(),Path::new("")).unwrap();
+    assert!(merger.add(b(),Path::new("")).is_err());
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr,OsString};
+use std::io::{BufRead,Write};
+use std::os::unix::ffi::{OsStrExt,OsStringExt};
+use std::path::{Path,PathBuf};
+use anyhow::{anyhow,Result};
+
+use crate::{
+    checksum::{parse_hex,to_hex},
+    diff,
+    fsmap::*,
+    import::{Builder,Kind}
+};
+
+// Device number given to the entries of a specification
+const MTREE_DEV : u64 = 1;
+
+// Escapes a name with octal sequences as vis does, so that it is a
+// single word without comment characters
+fn escape(name:&OsStr)->String {
+    let mut u = String::new();
+    for &c in name.as_bytes() {
+	if c <= b' ' || c >= 0x7f || c == b'\\' || c == b'#' {
+	    u.push_str(&format!("\\{:03o}",c));
+	} else {
+	    u.push(c as char);
+	}
+    }
+    u
+}
+
+fn unescape(u:&str)->OsString {
+    let b = u.as_bytes();
+    let mut name = Vec::new();
+    let mut i = 0;
+    while i < b.len() {
+	if b[i] != b'\\' || i + 1 == b.len() {
+	    name.push(b[i]);
+	    i += 1;
+	    continue;
+	}
+	let octal = b.get(i + 1..i + 4)
+	    .filter(|d| d.iter().all(|c| (b'0'..=b'7').contains(c)))
+	    .map(|d| d.iter().fold(0u32,|n,c| n * 8 + (c - b'0') as u32));
+	match octal {
+	    Some(c) => {
+		name.push(c as u8);
+		i += 4;
+	    },
+	    None => {
+		name.push(match b[i + 1] {
+		    b'n' => b'\n',
+		    b't' => b'\t',
+		    b's' => b' ',
+		    c => c
+		});
+		i += 2;
+	    }
+	}
+    }
+    OsString::from_vec(name)
+}
+
+struct Exporter<'a,W> {
+    mounts:&'a Mounts,
+    out:W
+}
+
+impl<'a,W:Write> Exporter<'a,W> {
+    fn dir(&mut self,path:&str,dir:&Directory)->Result<()> {
+	for (name,entry) in dir.entries.iter() {
+	    let path = format!("{}/{}",path,escape(name));
+	    match entry {
+		Entry::Dir(sub) => {
+		    writeln!(self.out,"{} type=dir",path)?;
+		    self.dir(&path,sub)?;
+		},
+		&Entry::File(ino) | &Entry::Archive(ino,_) => {
+		    write!(self.out,"{} type=file",path)?;
+		    if let Some(fi) = self.mounts.get_device(dir.dev).and_then(|d| d.get_inode(ino)) {
+			write!(self.out," size={} time={}.0",fi.size,fi.mtime.unwrap_or(fi.unix_time()))?;
+			if let Some(digest) = &fi.digest {
+			    write!(self.out," sha256digest={}",to_hex(digest))?;
+			}
+		    }
+		    writeln!(self.out)?;
+		},
+		Entry::Symlink(target) => writeln!(self.out,"{} type=link link={}",path,escape(target))?,
+		// The kind of other files is not known
+		Entry::Other(_) | Entry::Error(_) => ()
+	    }
+	}
+	Ok(())
+    }
+}
+
+/// Writes the entries of fs as an mtree specification, with one full
+/// path on each line.  Ownership and permissions are not in maps and
+/// are left out.
+pub fn export<W:Write>(fs:&FileSystem,mut out:W)->Result<()> {
+    writeln!(out,"#mtree")?;
+    if let Some(base) = &fs.base {
+	writeln!(out,"# {}",Path::new(base).display())?;
+    }
+    writeln!(out,". type=dir")?;
+    Exporter { mounts:&fs.mounts,out }.dir(".",&fs.root)
+}
+
+fn relative(path:&Path)->PathBuf {
+    path.components()
+	.filter(|c| matches!(c,std::path::Component::Normal(_)))
+	.collect()
+}
+
+/// Reads an mtree specification into a map.  The returned options
+/// leave out of the comparison the sizes and times that the
+/// specification does not give.
+pub fn parse<R:BufRead>(input:R)->Result<(FileSystem,diff::Options)> {
+    let mut builder = Builder::new(MTREE_DEV);
+    let mut options = diff::Options::default();
+    let mut defaults : HashMap<String,String> = HashMap::new();
+    let mut cwd = PathBuf::new();
+    let mut line = String::new();
+    for (iline,part) in input.lines().enumerate() {
+	let part = part?;
+	if let Some(head) = part.strip_suffix('\\') {
+	    line.push_str(head);
+	    continue;
+	}
+	line.push_str(&part);
+	let text = std::mem::take(&mut line);
+	let mut words = text.split_whitespace();
+	let Some(first) = words.next().filter(|w| !w.starts_with('#')) else { continue };
+	match first {
+	    "/set" => {
+		for w in words {
+		    if let Some((k,v)) = w.split_once('=') {
+			defaults.insert(k.to_string(),v.to_string());
+		    }
+		}
+		continue;
+	    },
+	    "/unset" => {
+		for w in words {
+		    if w == "all" {
+			defaults.clear();
+		    } else {
+			defaults.remove(w);
+		    }
+		}
+		continue;
+	    },
+	    ".." => {
+		cwd.pop();
+		continue;
+	    },
+	    _ => ()
+	}
+	let mut keywords = defaults.clone();
+	for w in words {
+	    if let Some((k,v)) = w.split_once('=') {
+		keywords.insert(k.to_string(),v.to_string());
+	    }
+	}
+	let name = unescape(first);
+	let kind = keywords.get("type").map_or("file",|t| t.as_str());
+	// Names with a slash are full paths, otherwise they are relative
+	// to the last directory entered
+	let path =
+	    if name.as_bytes().contains(&b'/') {
+		relative(Path::new(&name))
+	    } else {
+		let path = cwd.join(&name);
+		if kind == "dir" {
+		    cwd.push(&name);
+		}
+		relative(&path)
+	    };
+	if path.as_os_str().is_empty() {
+	    continue;
+	}
+	let size = keywords.get("size").and_then(|s| s.parse().ok());
+	let time = keywords.get("time")
+	    .and_then(|t| t.split('.').next())
+	    .and_then(|t| t.parse().ok());
+	let kind =
+	    match kind {
+		"dir" => Kind::Dir,
+		"link" => Kind::Symlink(keywords.get("link").map(|l| unescape(l)).unwrap_or_default()),
+		_ => {
+		    let ino = builder.inode(size.unwrap_or(0),time.unwrap_or(0));
+		    if size.is_none() {
+			options.unknown_size.insert((MTREE_DEV,ino));
+		    }
+		    if time.is_none() {
+			options.unknown_time.insert((MTREE_DEV,ino));
+		    }
+		    let digest = keywords.get("sha256digest").or(keywords.get("sha256"));
+		    if let Some(digest) = digest.and_then(|d| parse_hex(d)) {
+			builder.set_digest(ino,digest);
+		    }
+		    if kind == "file" { Kind::File(ino) } else { Kind::Other(ino) }
+		}
+	    };
+	builder.insert(&path,kind).map_err(|e| anyhow!("Line {}: {}",iline + 1,e))?;
+    }
+    Ok((builder.finish(None),options))
+}
+
+#[test]
+fn test_mtree() {
+    assert_eq!(unescape(&escape(OsStr::new("a b\\#c"))),"a b\\#c");
+    let spec = "#mtree
+/set type=file
+. type=dir
+a\\040b size=10 time=1552000000.5
+sub type=dir
+    c size=3 time=1552000000.0 \\
+      sha256digest=ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad
+    l type=link link=../c
+..
+./sub/d size=4 time=1552000000.0
+./sub/n
+";
+    let (fs,options) = parse(spec.as_bytes()).unwrap();
+    assert_eq!((options.unknown_size.len(),options.unknown_time.len()),(1,1));
+    let mut out = Vec::new();
+    export(&fs,&mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.contains("./a\\040b type=file size=10 time=1552000000.0\n"));
+    assert!(text.contains("./sub/l type=link link=../c\n"));
+    let (again,_) = parse(text.as_bytes()).unwrap();
+    assert!(diff::diff(&fs,&again,&options).is_empty());
+
+    let (other,_) = parse("./sub/c size=3 time=1552000000 sha256=00
+./sub/d size=5 time=1552000000
+./sub/e size=5 time=1552000000
+./sub/l type=link link=c
+./sub/n size=7 time=1552000000
+".as_bytes()).unwrap();
+    let changes = diff::diff(&fs,&other,&options);
+    let changes : Vec<(&str,&diff::Change)> = changes.iter()
+	.map(|(p,c)| (p.to_str().unwrap(),c))
+	.collect();
+    assert_eq!(changes,[("a b",&diff::Change::Missing),
+			("sub/d",&diff::Change::Size(4,5)),
+			("sub/l",&diff::Change::Target("../c".into(),"c".into())),
+			("sub/e",&diff::Change::Extra)]);
+}
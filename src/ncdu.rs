@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{Read,Write};
+use std::time::{SystemTime,UNIX_EPOCH};
+use anyhow::{bail,Result};
+use serde_json::{json,Map,Value};
+
+use crate::fsmap::*;
+
+/// Writer of the JSON format of ncdu, as read by ncdu -f
+struct Exporter<'a,W> {
+    fs:&'a FileSystem,
+    out:W,
+    // Number of entries referring to each inode
+    links:HashMap<(u64,u64),usize>
+}
+
+fn count_links(dir:&Directory,links:&mut HashMap<(u64,u64),usize>) {
+    for (_,entry) in dir.entries.iter() {
+	match entry {
+	    &Entry::File(ino) | &Entry::Other(ino) | &Entry::Archive(ino,_) =>
+		*links.entry((dir.dev,ino)).or_insert(0) += 1,
+	    Entry::Dir(sub) => count_links(sub,links),
+	    _ => ()
+	}
+    }
+}
+
+impl<'a,W:Write> Exporter<'a,W> {
+    fn inode(&self,info:&mut Map<String,Value>,dev:u64,ino:u64) {
+	let Some(fi) = self.fs.mounts.get_device(dev).and_then(|d| d.get_inode(ino)) else {
+	    return
+	};
+	info.insert("asize".into(),fi.size.into());
+	info.insert("dsize".into(),fi.size.into());
+	info.insert("ino".into(),ino.into());
+	info.insert("mtime".into(),fi.mtime.unwrap_or(fi.unix_time()).into());
+	let nlink = self.links.get(&(dev,ino)).copied().unwrap_or(1);
+	if nlink > 1 {
+	    info.insert("hlnkc".into(),true.into());
+	    info.insert("nlink".into(),nlink.into());
+	}
+    }
+
+    fn dir(&mut self,name:&str,dir:&Directory,parent_dev:Option<u64>)->Result<()> {
+	let mut info = Map::new();
+	info.insert("name".into(),name.into());
+	if parent_dev != Some(dir.dev) {
+	    info.insert("dev".into(),dir.dev.into());
+	}
+	write!(self.out,"[")?;
+	serde_json::to_writer(&mut self.out,&info)?;
+	for (name,entry) in dir.entries.iter() {
+	    let name = name.to_string_lossy();
+	    writeln!(self.out,",")?;
+	    if let Entry::Dir(sub) = entry {
+		self.dir(&name,sub,Some(dir.dev))?;
+		continue;
+	    }
+	    let mut info = Map::new();
+	    info.insert("name".into(),name.into());
+	    match entry {
+		// Archives are shown as plain files, without their members
+		&Entry::File(ino) | &Entry::Archive(ino,_) => self.inode(&mut info,dir.dev,ino),
+		&Entry::Other(ino) => {
+		    self.inode(&mut info,dir.dev,ino);
+		    info.insert("notreg".into(),true.into());
+		},
+		Entry::Symlink(target) => {
+		    info.insert("asize".into(),target.len().into());
+		    info.insert("notreg".into(),true.into());
+		},
+		_ => { info.insert("read_error".into(),true.into()); }
+	    }
+	    serde_json::to_writer(&mut self.out,&info)?;
+	}
+	write!(self.out,"]")?;
+	Ok(())
+    }
+}
+
+/// Writes fs in the JSON export format of ncdu
+pub fn export<W:Write>(fs:&FileSystem,out:W)->Result<()> {
+    let mut links = HashMap::new();
+    count_links(&fs.root,&mut links);
+    let mut ex = Exporter { fs,out,links };
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    write!(ex.out,"[1,2,")?;
+    serde_json::to_writer(&mut ex.out,&json!({
+	"progname":"fsmap",
+	"progver":env!("CARGO_PKG_VERSION"),
+	"timestamp":timestamp
+    }))?;
+    writeln!(ex.out,",")?;
+    let name = fs.base.as_ref().map_or("/".into(),|b| b.to_string_lossy());
+    ex.dir(&name,&fs.root,None)?;
+    writeln!(ex.out,"]")?;
+    Ok(())
+}
+
+/// Reader of ncdu exports
+struct Importer {
+    mounts:Mounts,
+    // Inodes of hard linked files, by device and inode number in the
+    // export
+    links:HashMap<(u64,u64),u64>
+}
+
+impl Importer {
+    fn inode(&mut self,dev:u64,info:&Map<String,Value>)->u64 {
+	let field = |name:&str| info.get(name).and_then(Value::as_u64);
+	let hard_link = info.get("hlnkc").and_then(Value::as_bool).unwrap_or(false);
+	let key = field("ino").filter(|_| hard_link).map(|ino| (dev,ino));
+	if let Some(&ino) = key.as_ref().and_then(|k| self.links.get(k)) {
+	    return ino;
+	}
+	self.mounts.ensure_device(dev);
+	let device = self.mounts.get_device_mut(dev).unwrap();
+	let ino = device.inodes.keys().next_back().map_or(1,|i| i + 1);
+	let mtime = info.get("mtime").and_then(Value::as_i64);
+	device.insert_inode(ino,FileInfo {
+	    size:field("asize").unwrap_or(0),
+	    time:(mtime.unwrap_or(0) / 60) as i32,
+	    digest:None,
+	    mtime
+	});
+	if let Some(key) = key {
+	    self.links.insert(key,ino);
+	}
+	ino
+    }
+
+    fn dir(&mut self,items:&[Value],parent_dev:u64)->Result<(String,Directory)> {
+	let Some(Value::Object(info)) = items.first() else {
+	    bail!("Directory without information")
+	};
+	let name = info.get("name").and_then(Value::as_str).unwrap_or_default();
+	let dev = info.get("dev").and_then(Value::as_u64).unwrap_or(parent_dev);
+	self.mounts.ensure_device(dev);
+	let mut dir = Directory::new(dev);
+	for item in items[1..].iter() {
+	    match item {
+		Value::Array(items) => {
+		    let (name,sub) = self.dir(items,dev)?;
+		    dir.insert(name.into(),Entry::Dir(sub));
+		},
+		Value::Object(info) => {
+		    let Some(name) = info.get("name").and_then(Value::as_str) else {
+			bail!("Entry without a name in {:?}",name)
+		    };
+		    let flag = |f:&str| info.get(f).and_then(Value::as_bool).unwrap_or(false);
+		    let entry =
+			if let Some(why) = info.get("excluded").and_then(Value::as_str) {
+			    Entry::Error(format!("Excluded ({})",why))
+			} else if flag("read_error") && !info.contains_key("asize") {
+			    Entry::Error("Read error".to_string())
+			} else if flag("notreg") {
+			    Entry::Other(self.inode(dev,info))
+			} else {
+			    Entry::File(self.inode(dev,info))
+			};
+		    dir.insert(OsString::from(name),entry);
+		},
+		_ => bail!("Unexpected {} in directory {:?}",item,name)
+	    }
+	}
+	Ok((name.to_string(),dir))
+    }
+}
+
+/// Builds a map from an ncdu export.  The name of the top directory
+/// becomes the base of the map.
+pub fn import<R:Read>(input:R)->Result<FileSystem> {
+    let top : Value = serde_json::from_reader(input)?;
+    let Some([major,_minor,_meta,Value::Array(root),..]) = top.as_array().map(|a| &a[..]) else {
+	bail!("Not an ncdu export")
+    };
+    if major.as_u64() != Some(1) {
+	bail!("Unsupported ncdu export version {}",major);
+    }
+    let mut imp = Importer { mounts:Mounts::new(),links:HashMap::new() };
+    let (name,root) = imp.dir(root,0)?;
+    let base = if name.starts_with('/') { Some(name.into()) } else { None };
+    Ok(FileSystem { mounts:imp.mounts,root,base })
+}
+
+#[test]
+fn test_ncdu() {
+    let mut mounts = Mounts::new();
+    mounts.ensure_device(7);
+    mounts.get_device_mut(7).unwrap().insert_inode(3,FileInfo { size:10,time:100,digest:None,mtime:None });
+    let mut root = Directory::new(7);
+    let mut sub = Directory::new(7);
+    sub.insert("b".into(),Entry::File(3));
+    root.insert("a".into(),Entry::File(3));
+    root.insert("d".into(),Entry::Dir(sub));
+    root.insert("l".into(),Entry::Symlink("a".into()));
+    root.insert("e".into(),Entry::Error("Permission denied".into()));
+    let fs = FileSystem { mounts,root,base:Some("/mnt".into()) };
+    let mut out = Vec::new();
+    export(&fs,&mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.contains(r#"{"asize":10,"dsize":10,"hlnkc":true,"ino":3,"mtime":6000,"name":"a","nlink":2}"#));
+    let fs = import(text.as_bytes()).unwrap();
+    assert_eq!(fs.base.as_deref(),Some(std::ffi::OsStr::new("/mnt")));
+    assert_eq!(fs.root.entries.len(),4);
+    let (&Entry::File(a),Entry::Dir(d)) = (&fs.root.entries[0].1,&fs.root.entries[1].1) else { panic!() };
+    let &Entry::File(b) = &d.entries[0].1 else { panic!() };
+    assert_eq!(a,b);
+    let fi = fs.mounts.get_device(7).unwrap().get_inode(a).unwrap();
+    assert_eq!((fi.size,fi.time),(10,100));
+    assert!(matches!(fs.root.entries[2].1,Entry::Other(_)));
+    assert!(matches!(fs.root.entries[3].1,Entry::Error(_)));
+}
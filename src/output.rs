@@ -0,0 +1,180 @@
+use anyhow::{bail,Result};
+use std::fs::{File,OpenOptions};
+use std::io::{self,BufWriter,Write};
+use std::path::PathBuf;
+use std::process::{Child,ChildStdin,Command,Stdio};
+
+/// Where the output of a command goes, as given by a trailing
+/// "> FILE", ">> FILE" or "! COMMAND"
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum Redirect {
+    Write(PathBuf),
+    Append(PathBuf),
+    Pipe(String)
+}
+
+/// Output of an interactive command
+pub enum Output {
+    Stdout(io::Stdout),
+    File(BufWriter<File>),
+    Pipe(Child,ChildStdin),
+    // Output to a terminal, held back until it is known whether it
+    // fits on the screen
+    Pager {
+	buf:Vec<u8>,
+	lines:usize,
+	height:usize
+    }
+}
+
+// Returns the byte offsets and characters of u that are outside of
+// quoted strings
+pub fn unquoted(u:&str)->impl Iterator<Item=(usize,char)> + '_ {
+    let mut quoted = false;
+    let mut escaped = false;
+    u.char_indices().filter(move |&(_,c)| {
+	match c {
+	    _ if escaped => escaped = false,
+	    '\\' if quoted => escaped = true,
+	    '\'' => quoted = !quoted,
+	    _ if !quoted => return true,
+	    _ => ()
+	}
+	false
+    })
+}
+
+impl Redirect {
+    /// Splits a trailing redirection off command line u
+    pub fn parse(u:&str)->Result<(&str,Option<Self>)> {
+	let Some((i,c)) = unquoted(u).find(|&(_,c)| c == '>' || c == '!') else {
+	    return Ok((u,None))
+	};
+	let (cmd,rest) = (&u[..i],&u[i + 1..]);
+	let redirect =
+	    match (c,rest.strip_prefix('>')) {
+		('>',Some(path)) => Self::Append(Self::target(path)?.into()),
+		('>',None) => Self::Write(Self::target(rest)?.into()),
+		_ => Self::Pipe(Self::target(rest)?.to_string())
+	    };
+	Ok((cmd,Some(redirect)))
+    }
+
+    fn target(u:&str)->Result<&str> {
+	let u = u.trim();
+	if u.is_empty() {
+	    bail!("Missing redirection target");
+	}
+	Ok(u)
+    }
+}
+
+impl Output {
+    /// Opens the output for redirect, or standard output.  If pager
+    /// is set and standard output is a terminal, output longer than
+    /// the terminal is sent to $PAGER.
+    pub fn open(redirect:Option<&Redirect>,pager:bool)->Result<Self> {
+	let out =
+	    match redirect {
+		Some(Redirect::Write(path)) =>
+		    Self::File(BufWriter::new(File::create(path)?)),
+		Some(Redirect::Append(path)) =>
+		    Self::File(BufWriter::new(
+			OpenOptions::new().create(true).append(true).open(path)?)),
+		Some(Redirect::Pipe(cmd)) => Self::pipe(cmd)?,
+		None =>
+		    match Self::terminal_height() {
+			Some(height) if pager =>
+			    Self::Pager { buf:Vec::new(),lines:0,height },
+			_ => Self::Stdout(io::stdout())
+		    }
+	    };
+	Ok(out)
+    }
+
+    fn pipe(cmd:&str)->io::Result<Self> {
+	let mut child = Command::new("sh")
+	    .arg("-c")
+	    .arg(cmd)
+	    .stdin(Stdio::piped())
+	    .spawn()?;
+	let stdin = child.stdin.take().unwrap();
+	Ok(Self::Pipe(child,stdin))
+    }
+
+    fn terminal_height()->Option<usize> {
+	unsafe {
+	    if libc::isatty(0) == 0 || libc::isatty(1) == 0 {
+		return None;
+	    }
+	    let mut ws : libc::winsize = std::mem::zeroed();
+	    if libc::ioctl(1,libc::TIOCGWINSZ,&mut ws) != 0 || ws.ws_row == 0 {
+		return None;
+	    }
+	    Some(ws.ws_row as usize)
+	}
+    }
+
+    /// Flushes the output and waits for the pager or the command
+    /// being piped to
+    pub fn finish(self)->Result<()> {
+	match self {
+	    Self::Stdout(mut out) => out.flush()?,
+	    Self::File(mut out) => out.flush()?,
+	    Self::Pipe(mut child,stdin) => {
+		drop(stdin);
+		child.wait()?;
+	    },
+	    Self::Pager { buf,.. } => {
+		let mut out = io::stdout();
+		out.write_all(&buf)?;
+		out.flush()?;
+	    }
+	}
+	Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self,data:&[u8])->io::Result<usize> {
+	match self {
+	    Self::Stdout(out) => out.write(data),
+	    Self::File(out) => out.write(data),
+	    Self::Pipe(_,stdin) => stdin.write(data),
+	    Self::Pager { buf,lines,height } => {
+		buf.extend_from_slice(data);
+		*lines += data.iter().filter(|&&c| c == b'\n').count();
+		// Keep one line for the prompt
+		if *lines >= *height {
+		    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".to_string());
+		    let buf = std::mem::take(buf);
+		    *self = Self::pipe(&pager)?;
+		    self.write_all(&buf)?;
+		}
+		Ok(data.len())
+	    }
+	}
+    }
+
+    fn flush(&mut self)->io::Result<()> {
+	match self {
+	    Self::Stdout(out) => out.flush(),
+	    Self::File(out) => out.flush(),
+	    Self::Pipe(_,stdin) => stdin.flush(),
+	    Self::Pager { .. } => Ok(())
+	}
+    }
+}
+
+#[test]
+fn test_redirect() {
+    for (u,cmd,r) in &[
+	("ll foo > out.txt","ll foo ",Some(Redirect::Write("out.txt".into()))),
+	("ll foo >>out.txt","ll foo ",Some(Redirect::Append("out.txt".into()))),
+	("ll '>' ! less -S","ll '>' ",Some(Redirect::Pipe("less -S".into()))),
+	("ll 'a\\'!'","ll 'a\\'!'",None)
+    ] {
+	assert_eq!(Redirect::parse(u).unwrap(),(*cmd,r.clone()));
+    }
+    assert!(Redirect::parse("ls >").is_err());
+}
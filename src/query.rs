@@ -0,0 +1,420 @@
+use anyhow::{bail,Result};
+use regex::{Regex,RegexSet,SetMatches};
+
+use crate::{
+    boolean::Expr,
+    fsexpr::{FsAtom,FsData,FsExpr,Predicate}
+};
+
+// Three-valued truth, used to decide whether entries below a
+// directory can possibly match
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+enum Tri {
+    No,
+    Maybe,
+    Yes
+}
+
+#[derive(Debug)]
+enum Node {
+    Const(bool),
+    // Atoms that only look at fields already present in FsData
+    Cheap(FsAtom),
+    // Index into the path or name regex set
+    Path(usize),
+    Name(usize),
+    // Remaining atoms (link resolution, target regexes)
+    Atom(FsAtom),
+    Contains(Box<Query>),
+    Under(Box<Query>),
+    And(Vec<Node>),
+    Or(Vec<Node>),
+    Not(Box<Node>)
+}
+
+// Literal prefix of a path regex anchored with ^
+#[derive(Debug)]
+struct Prefix {
+    literal:String,
+    // The regex consists only of the anchored literal
+    complete:bool,
+    // Same as above, with a $ anchor
+    exact:bool
+}
+
+/// An expression compiled for fast evaluation over many entries
+#[derive(Debug)]
+pub struct Query {
+    root:Node,
+    path_set:RegexSet,
+    name_set:RegexSet,
+    prefixes:Vec<Option<Prefix>>
+}
+
+#[derive(Default)]
+struct Matches {
+    path:Option<SetMatches>,
+    name:Option<SetMatches>
+}
+
+struct Compiler {
+    path_rxs:Vec<String>,
+    name_rxs:Vec<String>
+}
+
+impl Tri {
+    fn of_bool(b:bool)->Self {
+	if b { Self::Yes } else { Self::No }
+    }
+
+    fn and(self,other:Self)->Self {
+	match (self,other) {
+	    (Self::No,_) | (_,Self::No) => Self::No,
+	    (Self::Yes,Self::Yes) => Self::Yes,
+	    _ => Self::Maybe
+	}
+    }
+
+    fn or(self,other:Self)->Self {
+	match (self,other) {
+	    (Self::Yes,_) | (_,Self::Yes) => Self::Yes,
+	    (Self::No,Self::No) => Self::No,
+	    _ => Self::Maybe
+	}
+    }
+
+    fn not(self)->Self {
+	match self {
+	    Self::No => Self::Yes,
+	    Self::Maybe => Self::Maybe,
+	    Self::Yes => Self::No
+	}
+    }
+}
+
+impl Prefix {
+    fn of_regex(rx:&str)->Option<Self> {
+	if rx.contains('|') {
+	    return None;
+	}
+	let mut chars = rx.strip_prefix('^')?.chars().peekable();
+	let mut literal = String::new();
+	let mut complete = true;
+	let mut exact = false;
+	while let Some(c) = chars.next() {
+	    let lit =
+		match c {
+		    '$' if chars.peek().is_none() => {
+			exact = true;
+			break;
+		    },
+		    '.' | '*' | '+' | '?' | '(' | ')' | '[' | ']' |
+		    '{' | '}' | '^' | '$' => None,
+		    '\\' => chars.next().filter(|n| n.is_ascii_punctuation()),
+		    _ => Some(c)
+		};
+	    let Some(lit) = lit else {
+		complete = false;
+		break;
+	    };
+	    match chars.peek() {
+		Some('*' | '?' | '{') => {
+		    complete = false;
+		    break;
+		},
+		Some('+') => {
+		    literal.push(lit);
+		    complete = false;
+		    break;
+		},
+		_ => literal.push(lit)
+	    }
+	}
+	if literal.is_empty() {
+	    None
+	} else {
+	    Some(Self { literal,complete,exact:exact && complete })
+	}
+    }
+
+    // Whether paths strictly below dir can match
+    fn below(&self,dir:&str)->Tri {
+	let dir = format!("{}/",dir);
+	if dir.starts_with(&self.literal) {
+	    if self.complete && !self.exact {
+		Tri::Yes
+	    } else if self.exact {
+		Tri::No
+	    } else {
+		Tri::Maybe
+	    }
+	} else if self.literal.starts_with(&dir) {
+	    Tri::Maybe
+	} else {
+	    Tri::No
+	}
+    }
+}
+
+impl Node {
+    fn cost(&self)->usize {
+	match self {
+	    Self::Const(_) => 0,
+	    Self::Cheap(_) => 1,
+	    Self::Name(_) => 10,
+	    Self::Path(_) => 20,
+	    Self::Atom(FsAtom::TargetMatch(_)) => 20,
+	    Self::Atom(_) => 50,
+	    Self::Contains(_) | Self::Under(_) => 100,
+	    Self::And(xs) | Self::Or(xs) => xs.iter().map(|x| x.cost()).sum(),
+	    Self::Not(x) => x.cost()
+	}
+    }
+
+    fn and(xs:Vec<Node>)->Self {
+	let mut ys = Vec::new();
+	for x in xs {
+	    match x {
+		Self::Const(true) => (),
+		Self::Const(false) => return Self::Const(false),
+		Self::And(zs) => ys.extend(zs),
+		x => ys.push(x)
+	    }
+	}
+	Self::sorted(ys,Self::And,true)
+    }
+
+    fn or(xs:Vec<Node>)->Self {
+	let mut ys = Vec::new();
+	for x in xs {
+	    match x {
+		Self::Const(false) => (),
+		Self::Const(true) => return Self::Const(true),
+		Self::Or(zs) => ys.extend(zs),
+		x => ys.push(x)
+	    }
+	}
+	Self::sorted(ys,Self::Or,false)
+    }
+
+    fn sorted(mut xs:Vec<Node>,f:fn(Vec<Node>)->Node,empty:bool)->Self {
+	match xs.len() {
+	    0 => Self::Const(empty),
+	    1 => xs.pop().unwrap(),
+	    _ => {
+		xs.sort_by_key(|x| x.cost());
+		f(xs)
+	    }
+	}
+    }
+
+    fn not(x:Node)->Self {
+	match x {
+	    Self::Const(b) => Self::Const(!b),
+	    Self::Not(y) => *y,
+	    x => Self::Not(Box::new(x))
+	}
+    }
+}
+
+impl Compiler {
+    fn compile(&mut self,expr:&FsExpr)->Result<Node> {
+	let node =
+	    match expr {
+		Expr::True => Node::Const(true),
+		Expr::False => Node::Const(false),
+		Expr::Atom(a) => self.compile_atom(a)?,
+		Expr::And(x,y) => Node::and(vec![self.compile(x)?,self.compile(y)?]),
+		Expr::Or(x,y) => Node::or(vec![self.compile(x)?,self.compile(y)?]),
+		Expr::Diff(x,y) =>
+		    Node::and(vec![self.compile(x)?,Node::not(self.compile(y)?)])
+	    };
+	Ok(node)
+    }
+
+    fn compile_atom(&mut self,a:&FsAtom)->Result<Node> {
+	let node =
+	    match a {
+		FsAtom::PathMatch(rx) => Node::Path(Self::add(&mut self.path_rxs,rx)),
+		FsAtom::NameMatch(rx) => Node::Name(Self::add(&mut self.name_rxs,rx)),
+		FsAtom::Contains(e) => Node::Contains(Box::new(Query::compile(e)?)),
+		FsAtom::Under(e) => Node::Under(Box::new(Query::compile(e)?)),
+		FsAtom::TargetMatch(_) | FsAtom::Dangling => Node::Atom(a.clone()),
+		FsAtom::Var(name) => bail!("Variable ${} is not defined",name),
+		_ => Node::Cheap(a.clone())
+	    };
+	Ok(node)
+    }
+
+    fn add(rxs:&mut Vec<String>,rx:&Regex)->usize {
+	let u = rx.as_str();
+	match rxs.iter().position(|v| v == u) {
+	    Some(i) => i,
+	    None => {
+		rxs.push(u.to_string());
+		rxs.len() - 1
+	    }
+	}
+    }
+}
+
+impl Query {
+    pub fn compile(expr:&FsExpr)->Result<Self> {
+	let mut cc = Compiler { path_rxs:Vec::new(),name_rxs:Vec::new() };
+	let root = cc.compile(expr)?;
+	let prefixes = cc.path_rxs.iter().map(|rx| Prefix::of_regex(rx)).collect();
+	Ok(Self {
+	    root,
+	    path_set:RegexSet::new(&cc.path_rxs)?,
+	    name_set:RegexSet::new(&cc.name_rxs)?,
+	    prefixes
+	})
+    }
+
+    fn eval(&self,node:&Node,data:&FsData,m:&mut Matches)->bool {
+	match node {
+	    &Node::Const(b) => b,
+	    Node::Cheap(a) | Node::Atom(a) => a.eval(data),
+	    &Node::Path(i) =>
+		m.path.get_or_insert_with(|| self.path_set.matches(data.path))
+		.matched(i),
+	    &Node::Name(i) =>
+		m.name.get_or_insert_with(|| self.name_set.matches(data.name))
+		.matched(i),
+	    Node::Contains(q) => data.any_child(|d| q.test(d)),
+	    Node::Under(q) => data.ancestors().any(|d| q.test(&d)),
+	    Node::And(xs) => xs.iter().all(|x| self.eval(x,data,m)),
+	    Node::Or(xs) => xs.iter().any(|x| self.eval(x,data,m)),
+	    Node::Not(x) => !self.eval(x,data,m)
+	}
+    }
+
+    // Evaluates whether entries strictly below the directory
+    // described by data can match
+    fn below(&self,node:&Node,data:&FsData)->Tri {
+	match node {
+	    &Node::Const(b) => Tri::of_bool(b),
+	    &Node::Cheap(FsAtom::Drive(x)) => Tri::of_bool(data.drive == x),
+	    Node::Cheap(a @ FsAtom::DriveLabel(_)) => Tri::of_bool(a.eval(data)),
+	    &Node::Cheap(FsAtom::Depth(x)) =>
+		if data.depth < x { Tri::Maybe } else { Tri::No },
+	    &Node::Path(i) =>
+		self.prefixes[i].as_ref()
+		.map(|p| p.below(data.path))
+		.unwrap_or(Tri::Maybe),
+	    Node::Under(q) =>
+		if q.test(data) || data.ancestors().any(|d| q.test(&d)) {
+		    Tri::Yes
+		} else {
+		    Tri::Maybe
+		},
+	    Node::And(xs) =>
+		xs.iter().fold(Tri::Yes,|t,x| t.and(self.below(x,data))),
+	    Node::Or(xs) =>
+		xs.iter().fold(Tri::No,|t,x| t.or(self.below(x,data))),
+	    Node::Not(x) => self.below(x,data).not(),
+	    _ => Tri::Maybe
+	}
+    }
+}
+
+impl Predicate for Query {
+    fn test(&self,data:&FsData)->bool {
+	let mut m = Matches::default();
+	self.eval(&self.root,data,&mut m)
+    }
+
+    fn may_match_below(&self,data:&FsData)->bool {
+	self.below(&self.root,data) != Tri::No
+    }
+}
+
+#[test]
+fn test_prefix() {
+    for (rx,res) in &[
+	("^usr/share",Some(("usr/share",true,false))),
+	("^usr/share$",Some(("usr/share",true,true))),
+	("^usr/sh.re",Some(("usr/sh",false,false))),
+	("^usr/shares?",Some(("usr/share",false,false))),
+	("^usr/shar+",Some(("usr/shar",false,false))),
+	("^usr/lib\\.so",Some(("usr/lib.so",true,false))),
+	("^usr/\\d",Some(("usr/",false,false))),
+	("^usr|^opt",None),
+	("usr/share",None),
+	("^(?i)usr",None)
+    ] {
+	let p = Prefix::of_regex(rx)
+	    .map(|p| (p.literal,p.complete,p.exact));
+	println!("{:?} -> {:?}",rx,p);
+	assert_eq!(p,res.map(|(l,c,e)| (l.to_string(),c,e)));
+    }
+}
+
+#[cfg(test)]
+fn test_tree()->crate::fsmap::FileSystems {
+    crate::dumper::test_maps("d 0 a
+d 0 a/src
+f 3 a/src/main.rs
+d 0 a/src/.git
+d 0 b
+d 0 b/lib
+f 20000000 b/lib/big.bin
+f 0 b/lib/c.rs
+l 0 b/old -> /mnt/old-nas/x
+l 0 b/gone -> a/nothing
+d 0 cd
+f 0 cd/a
+f 2000000000 cd/huge
+f 1500000000000 cd/d.rs
+d 0 cd/b
+d 0 cd/b/src
+f 1 cd/b/src/d.rs")
+}
+
+#[test]
+fn test_query() {
+    use crate::{dumper::matching_paths,fsparser::TEST_EXPRESSIONS};
+    let fss = test_tree();
+    for u in TEST_EXPRESSIONS {
+	let expr = FsExpr::parse(u).unwrap();
+	// Variables are replaced before compiling
+	let Ok(query) = Query::compile(&expr) else { continue };
+	assert_eq!(matching_paths(&fss,&query),matching_paths(&fss,&expr),"{}",u);
+    }
+}
+
+#[test]
+fn test_prune() {
+    use crate::{dumper::Dumper,fsmap::*,sigint_detector::SigintDetector,watcher::{Action,Watcher}};
+    use std::ffi::OsString;
+    struct Check<'a>(&'a FsExpr,&'a Query,usize);
+    impl<'a> Watcher for Check<'a> {
+	fn matching_entry(&mut self,_fse:&FileSystemEntry,_name:&OsString,_device:&Device,
+			  _entry:&Entry,data:&FsData)->Result<Action> {
+	    if self.0.test(data) {
+		self.2 += 1;
+		assert!(data.ancestors().all(|d| self.1.may_match_below(&d)),"{}",data.path);
+	    }
+	    Ok(Action::Enter)
+	}
+    }
+    let fss = test_tree();
+    let all = FsExpr::parse("%t").unwrap();
+    for u in &[
+	"^a/src",
+	"^b/lib/.*rs$",
+	"^a/src$ | ^cd/",
+	"%depth 2 & ^b",
+	"^cd \\ %under %name ^b$",
+	"%under %name ^src$ & ^cd/b/",
+	"%contains %name ^main & ^a",
+	"'^(a|cd)/.*rs$' & %depth 3"
+    ] {
+	let expr = FsExpr::parse(u).unwrap();
+	let query = Query::compile(&expr).unwrap();
+	let sd = SigintDetector::new();
+	let mut dp = Dumper::new(&sd,&fss,&all,Check(&expr,&query,0));
+	dp.dump().unwrap();
+	assert!(dp.into_inner().2 > 0,"{}",u);
+    }
+}
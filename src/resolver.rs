@@ -0,0 +1,207 @@
+use std::collections::VecDeque;
+use std::ffi::{OsStr,OsString};
+use std::path::{Component,Path,PathBuf};
+
+use crate::{
+    fsexpr::FsData,
+    fsmap::*
+};
+
+// Same limit as Linux (MAXSYMLINKS)
+const MAX_LINKS : usize = 40;
+
+#[derive(Clone,Copy)]
+pub enum Node<'a> {
+    Dir(&'a Directory),
+    Other(&'a Entry)
+}
+
+pub enum Resolution<'a> {
+    // Path of the final entry, relative to the root of the map
+    Found(PathBuf,Node<'a>),
+    // Path of the first component that does not exist
+    Dangling(PathBuf),
+    // Absolute target that leaves the mapped tree
+    Outside(PathBuf),
+    // Too many levels of symbolic links
+    Loop
+}
+
+pub struct Resolved<'a> {
+    // Symbolic links followed, with their targets
+    pub hops:Vec<(PathBuf,OsString)>,
+    pub outcome:Resolution<'a>
+}
+
+pub struct Resolver<'a> {
+    fs:&'a FileSystem
+}
+
+impl<'a> Resolution<'a> {
+    pub fn is_dangling(&self)->bool {
+	matches!(self,Self::Dangling(_) | Self::Loop)
+    }
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(fs:&'a FileSystem)->Self {
+	Self { fs }
+    }
+
+    /// Resolves the target of the symbolic link described by data
+    pub fn resolve_link(&self,data:&FsData)->Option<Resolved<'a>> {
+	let Entry::Symlink(target) = data.entry else { return None };
+	let mut ancestors : Vec<FsData> = data.ancestors().collect();
+	ancestors.reverse();
+	let mut cwd = Vec::new();
+	for a in ancestors {
+	    let Entry::Dir(_) = a.entry else { return None };
+	    cwd.push(a.raw_name);
+	}
+	Some(self.resolve(&cwd,Path::new(target),true))
+    }
+
+    /// Resolves path, which may be relative to the directory whose
+    /// components are given by cwd
+    pub fn resolve(&self,cwd:&[&OsStr],path:&Path,follow_last:bool)
+		   ->Resolved<'a> {
+	let mut hops = Vec::new();
+	let outcome = self.walk(cwd,path,follow_last,&mut hops);
+	Resolved { hops,outcome }
+    }
+
+    fn walk<'p>(&self,
+		cwd:&[&OsStr],
+		path:&'p Path,
+		follow_last:bool,
+		hops:&mut Vec<(PathBuf,OsString)>)->Resolution<'a> where 'a:'p {
+	let mut stack : Vec<(&'a OsStr,&'a Directory)> = Vec::new();
+	for &name in cwd {
+	    let dir = stack.last().map(|&(_,d)| d).unwrap_or(&self.fs.root);
+	    match Self::lookup_named(dir,name) {
+		Some((name,Entry::Dir(d))) => stack.push((name,d)),
+		_ => return Resolution::Dangling(Self::path_of(&stack,Some(name)))
+	    }
+	}
+
+	let mut pending : VecDeque<Component<'p>> = VecDeque::new();
+	let mut seen : Vec<(PathBuf,VecDeque<Component<'p>>)> = Vec::new();
+	if let Err(outside) = self.push_components(&mut pending,&mut stack,path) {
+	    return outside;
+	}
+
+	while let Some(c) = pending.pop_front() {
+	    match c {
+		Component::Prefix(_) | Component::RootDir | Component::CurDir => (),
+		Component::ParentDir => {
+		    if stack.pop().is_none() {
+			if let Some(base) = &self.fs.base {
+			    return Resolution::Outside(Path::new(base).join(".."));
+			}
+		    }
+		},
+		Component::Normal(name) => {
+		    let dir = stack.last().map(|&(_,d)| d).unwrap_or(&self.fs.root);
+		    let Some((name,entry)) = Self::lookup_named(dir,name) else {
+			return Resolution::Dangling(Self::path_of(&stack,Some(name)));
+		    };
+		    let last = pending.is_empty();
+		    match entry {
+			Entry::Dir(d) => stack.push((name,d)),
+			Entry::Symlink(target) if !last || follow_last => {
+			    let link = Self::path_of(&stack,Some(name));
+			    let state = (link.clone(),pending.clone());
+			    if hops.len() >= MAX_LINKS || seen.contains(&state) {
+				return Resolution::Loop;
+			    }
+			    seen.push(state);
+			    hops.push((link,target.clone()));
+			    if let Err(outside) =
+				self.push_components(&mut pending,&mut stack,Path::new(target)) {
+				return outside;
+			    }
+			},
+			_ if last =>
+			    return Resolution::Found(Self::path_of(&stack,Some(name)),
+						     Node::Other(entry)),
+			_ => return Resolution::Dangling(Self::path_of(&stack,Some(name)))
+		    }
+		}
+	    }
+	}
+
+	let dir = stack.last().map(|&(_,d)| d).unwrap_or(&self.fs.root);
+	Resolution::Found(Self::path_of(&stack,None),Node::Dir(dir))
+    }
+
+    // Prepends the components of path to the pending queue.  Absolute
+    // paths are taken relative to the scan root when it is known, and
+    // to the root of the map otherwise.
+    fn push_components<'p>(&self,
+			   pending:&mut VecDeque<Component<'p>>,
+			   stack:&mut Vec<(&'a OsStr,&'a Directory)>,
+			   path:&'p Path)->Result<(),Resolution<'a>> {
+	let mut path = path;
+	if path.is_absolute() {
+	    if let Some(base) = &self.fs.base {
+		match path.strip_prefix(base) {
+		    Ok(rest) => path = rest,
+		    Err(_) => return Err(Resolution::Outside(path.to_path_buf()))
+		}
+	    }
+	    stack.clear();
+	}
+	for c in path.components().rev() {
+	    pending.push_front(c);
+	}
+	Ok(())
+    }
+
+    fn lookup_named(dir:&'a Directory,name:&OsStr)->Option<(&'a OsStr,&'a Entry)> {
+	dir.entries.iter()
+	    .find(|(n,_)| n == name)
+	    .map(|(n,e)| (n.as_os_str(),e))
+    }
+
+    fn path_of(stack:&[(&OsStr,&Directory)],last:Option<&OsStr>)->PathBuf {
+	let mut p = PathBuf::new();
+	for &(name,_) in stack {
+	    p.push(name);
+	}
+	if let Some(name) = last {
+	    p.push(name);
+	}
+	p
+    }
+}
+
+#[test]
+fn test_resolve() {
+    let fss = crate::dumper::test_maps("d 0 a
+d 0 a/b
+f 1 a/b/f
+l 0 a/up -> ../a/b/f
+l 0 a/b/chain -> ../up
+l 0 loop1 -> a/../loop2
+l 0 loop2 -> loop1
+l 0 dangling -> a/b/chain/x
+l 0 missing -> a/nothing
+l 0 abs -> /t/a/b/f
+l 0 out -> /etc/passwd");
+    let resolver = Resolver::new(&fss.systems[0].fs);
+    let resolve = |path:&str| resolver.resolve(&[],Path::new(path),true);
+    let res = resolve("a/b/chain");
+    assert_eq!(res.hops.len(),2);
+    assert!(matches!(res.outcome,Resolution::Found(p,Node::Other(Entry::File(_))) if p == Path::new("a/b/f")));
+    assert!(matches!(resolve("abs").outcome,Resolution::Found(p,_) if p == Path::new("a/b/f")));
+    assert!(matches!(resolve("loop1").outcome,Resolution::Loop));
+    assert!(matches!(resolve("dangling").outcome,Resolution::Dangling(p) if p == Path::new("a/b/f")));
+    assert!(matches!(resolve("missing").outcome,Resolution::Dangling(p) if p == Path::new("a/nothing")));
+    assert!(matches!(resolve("out").outcome,Resolution::Outside(_)));
+    assert!(matches!(resolve("a/b/../../a/b/chain").outcome,Resolution::Found(p,_) if p == Path::new("a/b/f")));
+    // Not following the last link
+    assert!(matches!(resolver.resolve(&[],Path::new("a/up"),false).outcome,
+		     Resolution::Found(p,Node::Other(Entry::Symlink(_))) if p == Path::new("a/up")));
+    let dangling = crate::fsexpr::FsExpr::parse("%dangling").unwrap();
+    assert_eq!(crate::dumper::matching_paths(&fss,&dangling),["dangling","loop1","loop2","missing"]);
+}
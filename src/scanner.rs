@@ -1,10 +1,12 @@
 use anyhow::{anyhow,Result};
-use std::ffi::OsString;
+use std::ffi::{OsStr,OsString};
 use std::fs::{DirEntry};
 use std::os::unix::fs::MetadataExt;
 use std::path::{Path,PathBuf};
 
 use crate::{
+    archive::{self,ArchiveFormat},
+    checksum::file_digest,
     fsmap::*
 };
 
@@ -16,17 +18,50 @@ pub trait Watcher {
 pub struct Scanner<W> {
     watcher:W,
     one_device:bool,
+    digest:bool,
+    descend_archives:bool,
+    // Number of archives listed, giving their virtual devices
+    archives:u64,
     device:Option<u64>
 }
 
 impl<W> Scanner<W> where W:Watcher {
-    pub fn new(watcher:W,one_device:bool)->Self {
+    pub fn new(watcher:W,one_device:bool,digest:bool)->Self {
 	Self {
 	    watcher,
 	    one_device,
+	    digest,
+	    descend_archives:false,
+	    archives:0,
 	    device:None
 	}
     }
+
+    /// Lists the members of archives found while scanning
+    pub fn set_descend_archives(&mut self,descend_archives:bool) {
+	self.descend_archives = descend_archives;
+    }
+
+    // Lists the members of the file at path if it is an archive, on a
+    // new virtual device
+    fn archive(&mut self,mounts:&mut Mounts,path:&Path,name:&OsStr)->Option<Directory> {
+	if !self.descend_archives {
+	    return None;
+	}
+	let format = ArchiveFormat::of_name(&name.to_string_lossy())?;
+	let dev = archive::ARCHIVE_DEV_BASE + self.archives;
+	match archive::list_members(path,format,dev) {
+	    Ok((dir,device)) => {
+		self.archives += 1;
+		mounts.devices.insert(dev,device);
+		Some(dir)
+	    },
+	    Err(_) => {
+		self.watcher.error(path);
+		None
+	    }
+	}
+    }
     
     fn scan_entry(&mut self,mounts:&mut Mounts,path:&Path,e:&DirEntry)
 		  ->Result<(Entry,OsString)> {
@@ -41,14 +76,23 @@ impl<W> Scanner<W> where W:Watcher {
 	    .ok_or_else(|| anyhow!("Cannot find device"))?;
 	let ino = md.ino();
 	if !d.has_inode(ino) {
-	    let fi = FileInfo::of_metadata(&md);
+	    let mut fi = FileInfo::of_metadata(&md);
+	    if self.digest && md.is_file() {
+		match file_digest(&sub_path) {
+		    Ok(digest) => fi.digest = Some(digest),
+		    Err(_) => self.watcher.error(&sub_path)
+		}
+	    }
 	    d.insert_inode(ino,fi);
 	}
 	let ent =
 	    if md.is_dir() {
 		self.scan(mounts,&sub_path)?
 	    } else if md.is_file() {
-		Entry::File(ino)
+		match self.archive(mounts,&sub_path,&name) {
+		    Some(members) => Entry::Archive(ino,members),
+		    None => Entry::File(ino)
+		}
 	    } else if md.is_symlink() {
 		let pb = e.path().read_link()?;
 		Entry::Symlink(pb.as_os_str().to_os_string())
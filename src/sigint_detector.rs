@@ -1,12 +1,10 @@
 use std::cell::Cell;
 use std::sync::atomic::{AtomicBool,Ordering};
 
-static mut INTERRUPTED : AtomicBool = AtomicBool::new(false);
+static INTERRUPTED : AtomicBool = AtomicBool::new(false);
 
 extern "C" fn sigint_handler(_sig:std::ffi::c_int) {
-    unsafe {
-	INTERRUPTED.store(true,Ordering::SeqCst);
-    }
+    INTERRUPTED.store(true,Ordering::SeqCst);
 }
 
 pub struct SigintDetector {
@@ -20,7 +18,7 @@ impl SigintDetector {
 	    unsafe {
 		let mut sa_mask : libc::sigset_t = std::mem::zeroed();
 		libc::sigemptyset(&mut sa_mask);
-		let sa_sigaction : libc::size_t = sigint_handler as libc::size_t;
+		let sa_sigaction : libc::size_t = sigint_handler as *const () as libc::size_t;
 
 		let act = libc::sigaction {
 		    sa_sigaction,
@@ -40,12 +38,11 @@ impl SigintDetector {
     }
 
     pub fn interrupted(&self)->bool {
-	if let Ok(true) = unsafe { INTERRUPTED.compare_exchange(
+	if let Ok(true) = INTERRUPTED.compare_exchange(
 	    true,
 	    false,
 	    Ordering::SeqCst,
-	    Ordering::SeqCst)
-	} {
+	    Ordering::SeqCst) {
 	    self.interrupted.set(true);
 	    true
 	} else {
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::Result;
+use rusqlite::{params,Connection,Statement};
+
+use crate::{
+    checksum::to_hex,
+    fsmap::*
+};
+
+const SCHEMA : &str = "
+PRAGMA journal_mode = OFF;
+PRAGMA synchronous = OFF;
+CREATE TABLE drives (
+  id INTEGER PRIMARY KEY,
+  origin TEXT NOT NULL,
+  label TEXT,
+  base TEXT
+);
+CREATE TABLE devices (
+  id INTEGER PRIMARY KEY,
+  drive INTEGER NOT NULL REFERENCES drives(id),
+  dev INTEGER NOT NULL
+);
+CREATE TABLE inodes (
+  device INTEGER NOT NULL REFERENCES devices(id),
+  ino INTEGER NOT NULL,
+  size INTEGER NOT NULL,
+  time INTEGER NOT NULL,
+  digest TEXT,
+  PRIMARY KEY (device,ino)
+);
+CREATE TABLE entries (
+  id INTEGER PRIMARY KEY,
+  drive INTEGER NOT NULL REFERENCES drives(id),
+  parent INTEGER REFERENCES entries(id),
+  name TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  device INTEGER REFERENCES devices(id),
+  ino INTEGER,
+  target TEXT
+);
+";
+
+// Created after the rows are inserted, which is faster
+const INDEXES : &str = "
+CREATE INDEX entries_parent ON entries(parent);
+CREATE INDEX entries_name ON entries(name);
+CREATE INDEX inodes_size ON inodes(size);
+CREATE INDEX inodes_time ON inodes(time);
+";
+
+/// Writer of the rows of the entries table, walking the tree of a map
+struct EntryWriter<'a> {
+    insert:Statement<'a>,
+    next_id:i64,
+    drive:i64,
+    // Row of each device of the drive
+    devices:HashMap<u64,i64>
+}
+
+impl<'a> EntryWriter<'a> {
+    fn row(&mut self,parent:Option<i64>,name:&str,kind:&str,
+	   dev:Option<u64>,ino:Option<u64>,target:Option<String>)->Result<i64> {
+	let id = self.next_id;
+	self.next_id += 1;
+	let device = dev.and_then(|d| self.devices.get(&d));
+	self.insert.execute(params![id,self.drive,parent,name,kind,device,
+				    ino.map(|i| i as i64),target])?;
+	Ok(id)
+    }
+
+    fn dir(&mut self,dir:&Directory,parent:i64)->Result<()> {
+	for (name,entry) in dir.entries.iter() {
+	    let name = name.to_string_lossy();
+	    let parent = Some(parent);
+	    match entry {
+		Entry::Dir(sub) => {
+		    let id = self.row(parent,&name,"dir",Some(sub.dev),None,None)?;
+		    self.dir(sub,id)?;
+		},
+		&Entry::File(ino) => { self.row(parent,&name,"file",Some(dir.dev),Some(ino),None)?; },
+		&Entry::Other(ino) => { self.row(parent,&name,"other",Some(dir.dev),Some(ino),None)?; },
+		Entry::Symlink(target) => {
+		    let target = target.to_string_lossy().into_owned();
+		    self.row(parent,&name,"symlink",None,None,Some(target))?;
+		},
+		Entry::Error(e) => { self.row(parent,&name,"error",None,None,Some(e.clone()))?; },
+		Entry::Archive(ino,members) => {
+		    let id = self.row(parent,&name,"archive",Some(dir.dev),Some(*ino),None)?;
+		    self.dir(members,id)?;
+		}
+	    }
+	}
+	Ok(())
+    }
+}
+
+/// Writes the maps into a new SQLite database at path.  Entries
+/// refer to their inode by device row and inode number; the target
+/// column holds the target of symbolic links and the message of
+/// errors.
+pub fn export(fss:&FileSystems,path:&Path)->Result<()> {
+    if path.exists() {
+	std::fs::remove_file(path)?;
+    }
+    let mut db = Connection::open(path)?;
+    db.execute_batch(SCHEMA)?;
+    let tx = db.transaction()?;
+    {
+	let mut insert_drive = tx.prepare("INSERT INTO drives VALUES (?1,?2,?3,?4)")?;
+	let mut insert_device = tx.prepare("INSERT INTO devices VALUES (?1,?2,?3)")?;
+	let mut insert_inode = tx.prepare("INSERT INTO inodes VALUES (?1,?2,?3,?4,?5)")?;
+	let mut next_device = 0;
+	let mut writer = EntryWriter {
+	    insert:tx.prepare("INSERT INTO entries VALUES (?1,?2,?3,?4,?5,?6,?7,?8)")?,
+	    next_id:0,
+	    drive:0,
+	    devices:HashMap::new()
+	};
+	for (idrive,fse) in fss.systems.iter().enumerate() {
+	    let drive = idrive as i64;
+	    let base = fse.fs.base.as_ref().map(|b| b.to_string_lossy());
+	    insert_drive.execute(params![drive,fse.origin.to_string_lossy(),fse.label,base])?;
+	    writer.drive = drive;
+	    writer.devices.clear();
+	    for (&dev,device) in fse.fs.mounts.devices.iter() {
+		insert_device.execute(params![next_device,drive,dev as i64])?;
+		writer.devices.insert(dev,next_device);
+		for (&ino,fi) in device.inodes.iter() {
+		    insert_inode.execute(params![next_device,ino as i64,fi.size as i64,
+						 fi.unix_time(),fi.digest.as_ref().map(to_hex)])?;
+		}
+		next_device += 1;
+	    }
+	    let root = writer.row(None,&base.unwrap_or_default(),"dir",Some(fse.fs.root.dev),None,None)?;
+	    writer.dir(&fse.fs.root,root)?;
+	}
+    }
+    tx.commit()?;
+    db.execute_batch(INDEXES)?;
+    Ok(())
+}
+
+#[test]
+fn test_sqlite() {
+    let mut mounts = Mounts::new();
+    mounts.ensure_device(7);
+    mounts.get_device_mut(7).unwrap().insert_inode(3,FileInfo { size:10,time:100,digest:None,mtime:None });
+    let mut root = Directory::new(7);
+    let mut sub = Directory::new(7);
+    sub.insert("b".into(),Entry::File(3));
+    root.insert("a".into(),Entry::File(3));
+    root.insert("d".into(),Entry::Dir(sub));
+    root.insert("l".into(),Entry::Symlink("a".into()));
+    let fs = FileSystem { mounts,root,base:Some("/mnt".into()) };
+    let fss = FileSystems { systems:vec![FileSystemEntry { origin:"t.mpk".into(),fs,label:None }] };
+    let path = std::env::temp_dir().join(format!("fsmap-test-{}.db",std::process::id()));
+    export(&fss,&path).unwrap();
+    let db = Connection::open(&path).unwrap();
+    let paths : Vec<(String,i64)> = db.prepare(
+	"SELECT p.name || '/' || e.name,i.size FROM entries e
+	 JOIN entries p ON e.parent = p.id
+	 JOIN inodes i USING (device,ino)
+	 ORDER BY e.id").unwrap()
+	.query_map([],|r| Ok((r.get(0)?,r.get(1)?))).unwrap()
+	.collect::<Result<_,_>>().unwrap();
+    assert_eq!(paths,[("/mnt/a".to_string(),10),("d/b".to_string(),10)]);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,280 @@
+use std::collections::{BTreeMap,HashMap,HashSet};
+use std::ffi::OsString;
+use std::io::Write;
+use anyhow::Result;
+use tz::UtcDateTime;
+
+use crate::{
+    fsexpr::FsData,
+    fsmap::*,
+    watcher::{Action,Watcher}
+};
+
+const TOP_EXTENSIONS : usize = 10;
+
+// Size buckets grow by factors of 16
+const BUCKET_BITS : u32 = 4;
+
+#[derive(Default,Clone,Copy)]
+pub struct Tally {
+    pub count:usize,
+    pub bytes:u64
+}
+
+/// Statistics over a set of entries
+#[derive(Default)]
+pub struct Summary {
+    files:usize,
+    dirs:usize,
+    symlinks:usize,
+    others:usize,
+    errors:usize,
+    // Sum of the sizes of all file entries
+    apparent_bytes:u64,
+    // Same, counting hard-linked inodes once
+    unique_bytes:u64,
+    sizes:BTreeMap<u32,Tally>,
+    years:BTreeMap<i32,Tally>,
+    extensions:HashMap<String,Tally>,
+    deepest:Option<(usize,String)>,
+    longest_name:Option<(usize,String)>
+}
+
+struct DriveStats {
+    name:String,
+    origin:OsString,
+    total:Summary,
+    devices:BTreeMap<u64,Summary>,
+    // Inodes already counted, by device, shared by the total and the
+    // summary of the device
+    inodes:HashMap<u64,HashSet<u64>>
+}
+
+/// Watcher gathering statistics per drive and per device
+#[derive(Default)]
+pub struct Stats {
+    drives:BTreeMap<usize,DriveStats>,
+    current:Option<usize>
+}
+
+impl Tally {
+    pub fn add(&mut self,bytes:u64) {
+	self.count += 1;
+	self.bytes += bytes;
+    }
+}
+
+/// Lowercase extension of a file name, without the dot.  Names
+/// starting with a dot and no other dot have no extension.
+pub fn extension(name:&str)->Option<String> {
+    match name.rsplit_once('.') {
+	Some((base,ext)) if !base.is_empty() && !ext.is_empty() =>
+	    Some(ext.to_lowercase()),
+	_ => None
+    }
+}
+
+/// Formats a number of bytes with a binary unit
+pub fn human_bytes(x:u64)->String {
+    const UNITS : &[&str] = &["B","KiB","MiB","GiB","TiB","PiB","EiB"];
+    let mut y = x as f64;
+    let mut i = 0;
+    while y >= 1024.0 && i + 1 < UNITS.len() {
+	y /= 1024.0;
+	i += 1;
+    }
+    if i == 0 {
+	format!("{} B",x)
+    } else {
+	format!("{:.1} {}",y,UNITS[i])
+    }
+}
+
+impl Summary {
+    // Adds an entry, whose inode is counted in the unique bytes if
+    // first is set
+    fn add(&mut self,data:&FsData,first:bool) {
+	match data.entry {
+	    Entry::Dir(_) => self.dirs += 1,
+	    Entry::File(_) | Entry::Archive(..) => {
+		self.files += 1;
+		let size = data.size.unwrap_or(0);
+		self.apparent_bytes += size;
+		if first {
+		    self.unique_bytes += size;
+		}
+		self.sizes.entry(Self::bucket(size)).or_default().add(size);
+		if let Some(year) = data.timestamp
+		    .and_then(|t| UtcDateTime::from_timespec(t,0).ok())
+		    .map(|dt| dt.year()) {
+			self.years.entry(year).or_default().add(size);
+		    }
+		let ext = match extension(data.name) {
+		    Some(ext) => format!(".{}",ext),
+		    None => "(none)".to_string()
+		};
+		self.extensions.entry(ext).or_default().add(size);
+	    },
+	    Entry::Symlink(_) => self.symlinks += 1,
+	    Entry::Other(_) => self.others += 1,
+	    Entry::Error(_) => self.errors += 1
+	}
+	if self.deepest.as_ref().map_or(true,|&(d,_)| d < data.depth) {
+	    self.deepest = Some((data.depth,data.path.to_string()));
+	}
+	let len = data.name.chars().count();
+	if self.longest_name.as_ref().map_or(true,|&(l,_)| l < len) {
+	    self.longest_name = Some((len,data.path.to_string()));
+	}
+    }
+
+    // Bucket k holds sizes below 16^k, bucket 0 empty files
+    fn bucket(size:u64)->u32 {
+	(64 - size.leading_zeros() + BUCKET_BITS - 1) / BUCKET_BITS
+    }
+
+    fn bucket_name(k:u32)->String {
+	match k {
+	    0 => "0".to_string(),
+	    _ if BUCKET_BITS * k >= 64 =>
+		format!(">= {}",human_bytes(1 << (BUCKET_BITS * (k - 1)))),
+	    _ => format!("< {}",human_bytes(1 << (BUCKET_BITS * k)))
+	}
+    }
+
+    fn write_counts<W:Write>(&self,out:&mut W,indent:&str)->Result<()> {
+	writeln!(out,"{}Files: {}, directories: {}, symlinks: {}, other: {}, errors: {}",
+		 indent,
+		 self.files,
+		 self.dirs,
+		 self.symlinks,
+		 self.others,
+		 self.errors)?;
+	writeln!(out,"{}Unique bytes: {} ({}), apparent bytes: {} ({})",
+		 indent,
+		 self.unique_bytes,
+		 human_bytes(self.unique_bytes),
+		 self.apparent_bytes,
+		 human_bytes(self.apparent_bytes))?;
+	Ok(())
+    }
+
+    fn write_tally<W:Write>(out:&mut W,key:&str,t:&Tally)->Result<()> {
+	writeln!(out,"    {:12} {:10} {:>12}",key,t.count,human_bytes(t.bytes))?;
+	Ok(())
+    }
+
+    fn write_details<W:Write>(&self,out:&mut W)->Result<()> {
+	if !self.sizes.is_empty() {
+	    writeln!(out,"  Sizes:")?;
+	    for (&k,t) in self.sizes.iter() {
+		Self::write_tally(out,&Self::bucket_name(k),t)?;
+	    }
+	}
+	if !self.years.is_empty() {
+	    writeln!(out,"  Years:")?;
+	    for (year,t) in self.years.iter() {
+		Self::write_tally(out,&year.to_string(),t)?;
+	    }
+	}
+	let mut exts : Vec<(&String,&Tally)> = self.extensions.iter().collect();
+	for (what,key) in [("count",(|t:&Tally| t.count as u64) as fn(&Tally)->u64),
+			   ("bytes",|t:&Tally| t.bytes)] {
+	    if exts.is_empty() {
+		break;
+	    }
+	    exts.sort_by(|(e1,t1),(e2,t2)| key(t2).cmp(&key(t1)).then(e1.cmp(e2)));
+	    writeln!(out,"  Top extensions by {}:",what)?;
+	    for (ext,t) in exts.iter().take(TOP_EXTENSIONS) {
+		Self::write_tally(out,ext,t)?;
+	    }
+	}
+	if let Some((depth,path)) = &self.deepest {
+	    writeln!(out,"  Deepest path: {} ({} levels)",path,depth)?;
+	}
+	if let Some((len,path)) = &self.longest_name {
+	    writeln!(out,"  Longest name: {} ({} characters)",path,len)?;
+	}
+	Ok(())
+    }
+}
+
+impl Stats {
+    pub fn new()->Self {
+	Self::default()
+    }
+
+    pub fn write<W:Write>(&self,out:&mut W)->Result<()> {
+	for (idrive,ds) in self.drives.iter() {
+	    if ds.name == idrive.to_string() {
+		writeln!(out,"Drive {} {:?}",idrive,ds.origin)?;
+	    } else {
+		writeln!(out,"Drive {} ({}) {:?}",idrive,ds.name,ds.origin)?;
+	    }
+	    ds.total.write_counts(out,"  ")?;
+	    if ds.devices.len() > 1 {
+		for (dev,summary) in ds.devices.iter() {
+		    writeln!(out,"  Device {}:",dev)?;
+		    summary.write_counts(out,"    ")?;
+		}
+	    }
+	    ds.total.write_details(out)?;
+	}
+	Ok(())
+    }
+}
+
+impl Watcher for Stats {
+    fn enter_fs(&mut self,ifs:usize,fse:&FileSystemEntry)->Result<Action> {
+	self.current = Some(ifs);
+	self.drives.entry(ifs).or_insert_with(|| DriveStats {
+	    name:fse.label.clone().unwrap_or_else(|| ifs.to_string()),
+	    origin:fse.origin.clone(),
+	    total:Summary::default(),
+	    devices:BTreeMap::new(),
+	    inodes:HashMap::new()
+	});
+	Ok(Action::Enter)
+    }
+
+    fn leave_fs(&mut self)->Result<()> {
+	self.current = None;
+	Ok(())
+    }
+
+    fn matching_entry(&mut self,
+		      _fse:&FileSystemEntry,
+		      _name:&OsString,
+		      _device:&Device,
+		      _entry:&Entry,
+		      data:&FsData)->Result<Action> {
+	if let Some(ds) = self.current.and_then(|i| self.drives.get_mut(&i)) {
+	    let dev = data.dev();
+	    let first = match data.entry {
+		&Entry::File(ino) | &Entry::Archive(ino,_) =>
+		    ds.inodes.entry(dev).or_default().insert(ino),
+		_ => false
+	    };
+	    ds.total.add(data,first);
+	    ds.devices.entry(dev).or_default().add(data,first);
+	}
+	Ok(Action::Enter)
+    }
+}
+
+#[test]
+fn test_buckets() {
+    for (size,k,name) in &[
+	(0,0,"0"),
+	(1,1,"< 16 B"),
+	(15,1,"< 16 B"),
+	(16,2,"< 256 B"),
+	(4096,4,"< 64.0 KiB"),
+	(u64::MAX,16,">= 1.0 EiB")
+    ] {
+	assert_eq!(Summary::bucket(*size),*k);
+	assert_eq!(Summary::bucket_name(*k),*name);
+    }
+    assert_eq!(extension("a.tar.GZ").as_deref(),Some("gz"));
+    assert_eq!(extension(".bashrc"),None);
+}
@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::Write;
+use anyhow::{bail,Result};
+use tz::UtcDateTime;
+
+use crate::{
+    fsexpr::FsData,
+    fsmap::*,
+    stats::{human_bytes,Tally},
+    watcher::{Action,Watcher}
+};
+
+const BAR_WIDTH : usize = 50;
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Period {
+    Year,
+    Month
+}
+
+/// Options of a timeline, given as -m (monthly), -c (CSV) and -d
+/// (per drive) in front of the expression
+#[derive(Debug,PartialEq,Eq)]
+pub struct TimelineSettings {
+    pub period:Period,
+    pub csv:bool,
+    pub by_drive:bool
+}
+
+/// Watcher totalling files by the period of their time stamp, the
+/// same time used by %before and %after
+pub struct Timeline {
+    settings:TimelineSettings,
+    // Drive names, when totals are kept per drive
+    drives:BTreeMap<usize,String>,
+    current:usize,
+    totals:BTreeMap<(usize,i32),Tally>
+}
+
+impl Default for TimelineSettings {
+    fn default()->Self {
+	Self { period:Period::Year,csv:false,by_drive:false }
+    }
+}
+
+impl TimelineSettings {
+    /// Splits leading options off w
+    pub fn parse(mut w:&str)->Result<(Self,&str)> {
+	let mut settings = Self::default();
+	loop {
+	    w = w.trim_start();
+	    let (opt,rest) = w.split_once(' ').unwrap_or((w,""));
+	    match opt {
+		"-m" => settings.period = Period::Month,
+		"-y" => settings.period = Period::Year,
+		"-c" => settings.csv = true,
+		"-d" => settings.by_drive = true,
+		_ if opt.starts_with('-') && opt.len() == 2 => bail!("Unknown option {}",opt),
+		_ => return Ok((settings,w))
+	    }
+	    w = rest;
+	}
+    }
+}
+
+impl Period {
+    fn of_timestamp(self,t:i64)->Option<i32> {
+	let dt = UtcDateTime::from_timespec(t,0).ok()?;
+	match self {
+	    Self::Year => Some(dt.year()),
+	    Self::Month => Some(dt.year() * 12 + dt.month() as i32 - 1)
+	}
+    }
+
+    fn name(self,k:i32)->String {
+	match self {
+	    Self::Year => k.to_string(),
+	    Self::Month => format!("{:04}-{:02}",k.div_euclid(12),k.rem_euclid(12) + 1)
+	}
+    }
+}
+
+impl Timeline {
+    pub fn new(settings:TimelineSettings)->Self {
+	Self {
+	    settings,
+	    drives:BTreeMap::new(),
+	    current:0,
+	    totals:BTreeMap::new()
+	}
+    }
+
+    pub fn write<W:Write>(&self,out:&mut W)->Result<()> {
+	if self.settings.csv {
+	    self.write_csv(out)
+	} else {
+	    self.write_bars(out)
+	}
+    }
+
+    fn write_csv<W:Write>(&self,out:&mut W)->Result<()> {
+	if self.settings.by_drive {
+	    writeln!(out,"drive,period,files,bytes")?;
+	} else {
+	    writeln!(out,"period,files,bytes")?;
+	}
+	for (&(idrive,k),t) in self.totals.iter() {
+	    if self.settings.by_drive {
+		write!(out,"{},",Self::csv_field(&self.drives[&idrive]))?;
+	    }
+	    writeln!(out,"{},{},{}",self.settings.period.name(k),t.count,t.bytes)?;
+	}
+	Ok(())
+    }
+
+    fn csv_field(u:&str)->String {
+	if u.contains([',','"','\n']) {
+	    format!("\"{}\"",u.replace('"',"\"\""))
+	} else {
+	    u.to_string()
+	}
+    }
+
+    // Periods without files between the first and the last one are
+    // shown as empty bars
+    fn write_bars<W:Write>(&self,out:&mut W)->Result<()> {
+	let max = self.totals.values().map(|t| t.bytes).max().unwrap_or(0);
+	let mut drives : Vec<usize> = self.totals.keys().map(|&(i,_)| i).collect();
+	drives.dedup();
+	for idrive in drives {
+	    if self.settings.by_drive {
+		writeln!(out,"Drive {}:",self.drives[&idrive])?;
+	    }
+	    let range = self.totals.range((idrive,i32::MIN)..=(idrive,i32::MAX));
+	    let first = range.clone().next().map(|(&(_,k),_)| k).unwrap_or(0);
+	    let last = range.clone().next_back().map(|(&(_,k),_)| k).unwrap_or(0);
+	    for k in first..=last {
+		let t = self.totals.get(&(idrive,k)).copied().unwrap_or_default();
+		let width =
+		    if max > 0 {
+			((t.bytes as f64 / max as f64) * BAR_WIDTH as f64).ceil() as usize
+		    } else {
+			0
+		    };
+		writeln!(out,"{:>7} {:10} {:>12} {}",
+			 self.settings.period.name(k),
+			 t.count,
+			 human_bytes(t.bytes),
+			 "#".repeat(width))?;
+	    }
+	}
+	Ok(())
+    }
+}
+
+impl Watcher for Timeline {
+    fn enter_fs(&mut self,ifs:usize,fse:&FileSystemEntry)->Result<Action> {
+	if self.settings.by_drive {
+	    self.current = ifs;
+	    self.drives.insert(ifs,fse.label.clone().unwrap_or_else(|| ifs.to_string()));
+	}
+	Ok(Action::Enter)
+    }
+
+    fn matching_entry(&mut self,
+		      _fse:&FileSystemEntry,
+		      _name:&OsString,
+		      _device:&Device,
+		      entry:&Entry,
+		      data:&FsData)->Result<Action> {
+	if let (Entry::File(_) | Entry::Archive(..),Some(t)) = (entry,data.timestamp) {
+	    if let Some(k) = self.settings.period.of_timestamp(t) {
+		self.totals.entry((self.current,k)).or_default()
+		    .add(data.size.unwrap_or(0));
+	    }
+	}
+	Ok(Action::Enter)
+    }
+}
+
+#[test]
+fn test_timeline_settings() {
+    let (s,w) = TimelineSettings::parse("-m -c %larger 1G").unwrap();
+    assert_eq!(s,TimelineSettings { period:Period::Month,csv:true,by_drive:false });
+    assert_eq!(w,"%larger 1G");
+    let (s,w) = TimelineSettings::parse("-d").unwrap();
+    assert!(s.by_drive);
+    assert_eq!(w,"");
+    assert!(TimelineSettings::parse("-x foo").is_err());
+    assert_eq!(TimelineSettings::parse("-foo$").unwrap().1,"-foo$");
+    let k = Period::Month.of_timestamp(1_552_000_000).unwrap();
+    assert_eq!(Period::Month.name(k),"2019-03");
+}
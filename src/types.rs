@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::{Path,PathBuf};
+use anyhow::{bail,Result};
+
+use crate::{
+    fsexpr::FsData,
+    fsmap::*,
+    stats::{extension,human_bytes,Tally},
+    watcher::{Action,Watcher}
+};
+
+const DEFAULT_CATEGORIES : &[(&str,&str)] = &[
+    ("video","mkv mp4 avi mov wmv flv webm m4v mpg mpeg vob"),
+    ("image","jpg jpeg png gif bmp tif tiff webp heic raw cr2 nef arw dng svg"),
+    ("audio","mp3 flac ogg wav m4a aac opus wma"),
+    ("archive","zip tar gz tgz bz2 xz 7z rar zst iso dmg deb rpm"),
+    ("vm","vmdk vdi qcow2 vhd vhdx ova"),
+    ("code","rs c h cc cpp hpp py js ts java go rb pl pm sh ml hs el lua"),
+    ("document","pdf doc docx odt txt md tex epub xls xlsx ods ppt pptx")
+];
+
+/// Named sets of extensions
+pub struct Categories {
+    names:Vec<String>,
+    by_ext:HashMap<String,usize>
+}
+
+/// How entries are grouped in a type report
+pub enum Grouping {
+    Extension,
+    Category(Categories)
+}
+
+/// Watcher totalling the files matched by type
+pub struct TypeReport {
+    grouping:Grouping,
+    totals:HashMap<String,Tally>
+}
+
+impl Categories {
+    /// Location of the category definitions, ~/.fsmap/categories
+    pub fn default_path()->PathBuf {
+	let mut path = PathBuf::new();
+	if let Some(dir) = std::env::var_os("HOME") {
+	    path.push(dir);
+	}
+	path.join(".fsmap").join("categories")
+    }
+
+    /// Loads categories given as lines of the form
+    /// "NAME = EXT1 EXT2 ...", or the built-in ones if the file does
+    /// not exist.  An extension belongs to the first category
+    /// listing it.
+    pub fn load<P:AsRef<Path>>(path:P)->Result<Self> {
+	let path = path.as_ref();
+	if !path.exists() {
+	    return Ok(Self::parse(DEFAULT_CATEGORIES.iter().copied()));
+	}
+	let text = fs::read_to_string(path)?;
+	let mut defs = Vec::new();
+	for (iline,line) in text.lines().enumerate() {
+	    let line = line.trim();
+	    if line.is_empty() || line.starts_with('#') {
+		continue;
+	    }
+	    let Some((name,exts)) = line.split_once('=') else {
+		bail!("{}: line {}: expected NAME = EXTENSIONS",path.display(),iline + 1)
+	    };
+	    defs.push((name.trim(),exts));
+	}
+	if defs.is_empty() {
+	    bail!("{}: no categories defined",path.display());
+	}
+	Ok(Self::parse(defs.into_iter()))
+    }
+
+    fn parse<'a,I:Iterator<Item=(&'a str,&'a str)>>(defs:I)->Self {
+	let mut names = Vec::new();
+	let mut by_ext = HashMap::new();
+	for (name,exts) in defs {
+	    for ext in exts.split_whitespace() {
+		let ext = ext.trim_start_matches('.').to_lowercase();
+		by_ext.entry(ext).or_insert(names.len());
+	    }
+	    names.push(name.to_string());
+	}
+	Self { names,by_ext }
+    }
+
+    pub fn category(&self,ext:&str)->Option<&str> {
+	self.by_ext.get(ext).map(|&i| self.names[i].as_str())
+    }
+}
+
+impl TypeReport {
+    pub fn new(grouping:Grouping)->Self {
+	Self { grouping,totals:HashMap::new() }
+    }
+
+    fn key(&self,name:&str)->String {
+	let ext = extension(name);
+	match (&self.grouping,ext) {
+	    (Grouping::Extension,Some(ext)) => format!(".{}",ext),
+	    (Grouping::Extension,None) => "(none)".to_string(),
+	    (Grouping::Category(cats),ext) =>
+		ext.as_deref()
+		.and_then(|ext| cats.category(ext))
+		.unwrap_or("other")
+		.to_string()
+	}
+    }
+
+    /// Writes the totals by decreasing number of bytes
+    pub fn write<W:Write>(&self,out:&mut W)->Result<()> {
+	let mut rows : Vec<(&String,&Tally)> = self.totals.iter().collect();
+	rows.sort_by(|(k1,t1),(k2,t2)| {
+	    t2.bytes.cmp(&t1.bytes)
+		.then(t2.count.cmp(&t1.count))
+		.then(k1.cmp(k2))
+	});
+	let total = rows.iter().fold(Tally::default(),|mut s,(_,t)| {
+	    s.count += t.count;
+	    s.bytes += t.bytes;
+	    s
+	});
+	writeln!(out,"{:>10} {:>12} {:>6}  Type","Files","Bytes","%")?;
+	for (key,t) in rows.iter() {
+	    let pct =
+		if total.bytes > 0 {
+		    100.0 * t.bytes as f64 / total.bytes as f64
+		} else {
+		    0.0
+		};
+	    writeln!(out,"{:10} {:>12} {:6.1}  {}",t.count,human_bytes(t.bytes),pct,key)?;
+	}
+	writeln!(out,"{:10} {:>12} {:>6}  Total",total.count,human_bytes(total.bytes),"")?;
+	Ok(())
+    }
+}
+
+impl Watcher for TypeReport {
+    fn matching_entry(&mut self,
+		      _fse:&FileSystemEntry,
+		      _name:&OsString,
+		      _device:&Device,
+		      entry:&Entry,
+		      data:&FsData)->Result<Action> {
+	if let Entry::File(_) | Entry::Archive(..) = entry {
+	    let key = self.key(data.name);
+	    self.totals.entry(key).or_default().add(data.size.unwrap_or(0));
+	}
+	Ok(Action::Enter)
+    }
+}
+
+#[test]
+fn test_categories() {
+    let cats = Categories::parse(DEFAULT_CATEGORIES.iter().copied());
+    let report = TypeReport::new(Grouping::Category(cats));
+    assert_eq!(report.key("Holiday.MKV"),"video");
+    assert_eq!(report.key("main.rs"),"code");
+    assert_eq!(report.key("index.ts"),"code");
+    assert_eq!(report.key("Makefile"),"other");
+    let cats = Categories::parse([("src","rs .C"),("rust","rs toml")].into_iter());
+    assert_eq!(cats.category("c"),Some("src"));
+    assert_eq!(cats.category("rs"),Some("src"));
+    assert_eq!(cats.category("toml"),Some("rust"));
+}
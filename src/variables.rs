@@ -0,0 +1,233 @@
+use anyhow::{bail,Result};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead,BufReader,BufWriter,Write};
+use std::path::Path;
+use log::warn;
+
+use crate::{
+    boolean::Expr,
+    fsexpr::{FsAtom,FsExpr}
+};
+
+pub struct Definition {
+    pub source:String,
+    pub expr:FsExpr
+}
+
+/// Named expressions, referenced as $NAME in other expressions
+#[derive(Default)]
+pub struct Variables {
+    defs:BTreeMap<String,Definition>
+}
+
+impl Variables {
+    pub fn new()->Self {
+	Self::default()
+    }
+
+    pub fn is_valid_name(name:&str)->bool {
+	let mut chars = name.chars();
+	chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') &&
+	    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    pub fn iter(&self)->impl Iterator<Item=(&String,&Definition)> {
+	self.defs.iter()
+    }
+
+    /// Defines or redefines name, rejecting definitions that refer
+    /// to undefined variables or that would create a cycle
+    pub fn define(&mut self,name:&str,source:&str)->Result<()> {
+	if !Self::is_valid_name(name) {
+	    bail!("Invalid variable name {:?}",name);
+	}
+	let expr = FsExpr::parse(source)?;
+	let def = Definition { source:source.to_string(),expr };
+	let old = self.defs.insert(name.to_string(),def);
+	if let Err(e) = self.expand_var(name,&mut Vec::new()) {
+	    match old {
+		Some(old) => self.defs.insert(name.to_string(),old),
+		None => self.defs.remove(name)
+	    };
+	    return Err(e);
+	}
+	Ok(())
+    }
+
+    pub fn undefine(&mut self,name:&str)->Result<()> {
+	let Some(def) = self.defs.remove(name) else {
+	    bail!("Variable ${} is not defined",name)
+	};
+	let users : Vec<String> = self.defs.iter()
+	    .filter(|(_,d)| Self::refers_to(&d.expr,name))
+	    .map(|(n,_)| format!("${}",n))
+	    .collect();
+	if !users.is_empty() {
+	    let msg = format!("Variable ${} is used by {}",name,users.join(", "));
+	    self.defs.insert(name.to_string(),def);
+	    bail!(msg);
+	}
+	Ok(())
+    }
+
+    /// Replaces references to variables by their definitions
+    pub fn expand(&self,expr:&FsExpr)->Result<FsExpr> {
+	self.expand_with(expr,&mut Vec::new())
+    }
+
+    fn expand_with(&self,expr:&FsExpr,stack:&mut Vec<String>)->Result<FsExpr> {
+	expr.try_map(&mut |a| {
+	    let e =
+		match a {
+		    FsAtom::Var(name) => self.expand_var(name,stack)?,
+		    FsAtom::Contains(x) =>
+			Expr::Atom(FsAtom::Contains(Box::new(self.expand_with(x,stack)?))),
+		    FsAtom::Under(x) =>
+			Expr::Atom(FsAtom::Under(Box::new(self.expand_with(x,stack)?))),
+		    a => Expr::Atom(a.clone())
+		};
+	    Ok(e)
+	})
+    }
+
+    fn expand_var(&self,name:&str,stack:&mut Vec<String>)->Result<FsExpr> {
+	if stack.iter().any(|n| n == name) {
+	    let cycle : Vec<String> = stack.iter()
+		.chain(std::iter::once(&name.to_string()))
+		.map(|n| format!("${}",n))
+		.collect();
+	    bail!("Cyclic definition {}",cycle.join(" -> "));
+	}
+	let Some(def) = self.defs.get(name) else {
+	    bail!("Variable ${} is not defined",name)
+	};
+	stack.push(name.to_string());
+	let e = self.expand_with(&def.expr,stack)?;
+	stack.pop();
+	Ok(e)
+    }
+
+    fn refers_to(expr:&FsExpr,name:&str)->bool {
+	let mut found = false;
+	let _ : Result<FsExpr,()> = expr.try_map(&mut |a| {
+	    match a {
+		FsAtom::Var(n) => found |= n == name,
+		FsAtom::Contains(x) | FsAtom::Under(x) =>
+		    found |= Self::refers_to(x,name),
+		_ => ()
+	    }
+	    Ok(Expr::True)
+	});
+	found
+    }
+
+    /// Loads definitions of the form "let NAME = EXPR", one per line.
+    /// Invalid definitions are reported and skipped.
+    pub fn load<P:AsRef<Path>>(&mut self,path:P)->Result<()> {
+	let fd = File::open(path)?;
+	let mut defs = Vec::new();
+	for line in BufReader::new(fd).lines() {
+	    let line = line?;
+	    let line = line.trim();
+	    if line.is_empty() || line.starts_with('#') {
+		continue;
+	    }
+	    match Self::parse_let(line) {
+		Some((name,source)) => defs.push((name.to_string(),source.to_string())),
+		None => warn!("Ignoring invalid line {:?}",line)
+	    }
+	}
+
+	// Definitions may refer to variables defined further down
+	for (name,source) in defs.iter() {
+	    match FsExpr::parse(source) {
+		Ok(expr) => {
+		    let def = Definition { source:source.clone(),expr };
+		    self.defs.insert(name.clone(),def);
+		},
+		Err(e) => warn!("Ignoring definition of ${}: {}",name,e)
+	    }
+	}
+	let bad : Vec<String> = self.defs.keys()
+	    .filter(|name| match self.expand_var(name,&mut Vec::new()) {
+		Ok(_) => false,
+		Err(e) => {
+		    warn!("Ignoring definition of ${}: {}",name,e);
+		    true
+		}
+	    })
+	    .cloned()
+	    .collect();
+	for name in bad {
+	    self.defs.remove(&name);
+	}
+	Ok(())
+    }
+
+    /// Writes the definition of name to the file at path, replacing
+    /// the line defining it, or removing it if name is no longer
+    /// defined.  The other lines of the file are kept as they are.
+    pub fn save<P:AsRef<Path>>(&self,path:P,name:&str)->Result<()> {
+	let path = path.as_ref();
+	let mut lines = Vec::new();
+	if path.exists() {
+	    for line in BufReader::new(File::open(path)?).lines() {
+		lines.push(line?);
+	    }
+	} else {
+	    lines.push("# Variables defined in fsmap examine".to_string());
+	}
+	let def = self.defs.get(name).map(|d| format!("let {} = {}",name,d.source));
+	let defines = |line:&str| {
+	    let line = line.trim();
+	    !line.starts_with('#') &&
+		Self::parse_let(line).is_some_and(|(n,_)| n == name)
+	};
+	match (lines.iter().position(|l| defines(l)),def) {
+	    (Some(i),Some(def)) => lines[i] = def,
+	    (Some(i),None) => { lines.remove(i); },
+	    (None,Some(def)) => lines.push(def),
+	    (None,None) => ()
+	}
+	let mut buf = BufWriter::new(File::create(path)?);
+	for line in lines {
+	    writeln!(buf,"{}",line)?;
+	}
+	buf.flush()?;
+	Ok(())
+    }
+
+    /// Splits "NAME = EXPR" or "let NAME = EXPR"
+    pub fn parse_let(u:&str)->Option<(&str,&str)> {
+	let u = u.strip_prefix("let ").unwrap_or(u);
+	let (name,source) = u.split_once('=')?;
+	Some((name.trim(),source.trim()))
+    }
+}
+
+#[test]
+fn test_variables() {
+    let mut vars = Variables::new();
+    vars.define("media",r"'\.(mkv|mp4)$'").unwrap();
+    vars.define("junk","%name ^node_modules$ | %name ^target$").unwrap();
+    vars.define("a","$media \\ %under $junk").unwrap();
+    assert!(vars.define("b","$undefined").is_err());
+    assert!(vars.define("media","$a").is_err());
+    assert!(vars.define("c","$c").is_err());
+    assert_eq!(vars.defs["media"].source,r"'\.(mkv|mp4)$'");
+    assert!(vars.undefine("junk").is_err());
+    vars.undefine("a").unwrap();
+    vars.undefine("junk").unwrap();
+    let e = vars.expand(&FsExpr::parse("$media & %larger 1G").unwrap()).unwrap();
+    println!("{:?}",e);
+    assert!(!Variables::refers_to(&e,"media"));
+
+    let path = std::env::temp_dir().join(format!("fsmaprc-test-{}",std::process::id()));
+    std::fs::write(&path,"# Mine\nlet media = %name x\nlet junk = %name y\n").unwrap();
+    vars.save(&path,"media").unwrap();
+    vars.save(&path,"junk").unwrap();
+    let text = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(text,"# Mine\nlet media = '\\.(mkv|mp4)$'\n");
+}
@@ -15,10 +15,7 @@ pub enum Action {
 
 impl Action {
     pub fn is_skip(&self)->bool {
-	match self {
-	    Self::Skip => true,
-	    _ => false
-	}
+	matches!(self,Self::Skip)
     }
 }
 